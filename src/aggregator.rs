@@ -0,0 +1,713 @@
+use crate::columnar::{self, Columnar, ColumnarWriter};
+use crate::dialect::{self, Dialect};
+use crate::encoding::OutputEncoding;
+use crate::header::{self, ColumnMap, HeaderDiff};
+use crate::output::{JsonWriter, RecordWriter};
+use crate::source::{self, SourceColumns};
+use crate::sqlite::SqliteWriter;
+use crate::{
+    csv_reader, discovery, json, open_input, parallel, Error, ErrorMode, Format, HeaderMode,
+    LineEnding, Opened, QuoteStyle, Result,
+};
+use encoding_rs::Encoding;
+use regex::Regex;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Aggregates an input CSV file from each of a set of folders into a single
+/// output, adding columns that describe the folder each row originated from.
+///
+/// # Examples
+///
+/// ```no_run
+/// use alligregator::Aggregator;
+///
+/// let summary = Aggregator::new("data.csv")
+///     .root("exports")
+///     .folders(["2024-01", "2024-02"])
+///     .column("month")
+///     .run(std::io::stdout())?;
+/// println!("Aggregated {} rows", summary.rows);
+/// # Ok::<(), alligregator::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    filename: String,
+    root: PathBuf,
+    folders: Vec<String>,
+    globs: Vec<String>,
+    recursive: bool,
+    column: Option<String>,
+    partitions: bool,
+    pattern: Option<Regex>,
+    input_encoding: Option<&'static Encoding>,
+    error_mode: ErrorMode,
+    on_skip: Option<SkipHook>,
+    header_mode: HeaderMode,
+    delimiter: u8,
+    sniff: bool,
+    format: Format,
+    output_delimiter: u8,
+    output_encoding: OutputEncoding,
+    quote_style: QuoteStyle,
+    line_ending: LineEnding,
+    table: Option<String>,
+    append: bool,
+    threads: usize,
+    ordered: bool,
+}
+
+/// A function that's called with the error for each folder that's skipped.
+#[derive(Clone)]
+struct SkipHook(Arc<dyn Fn(&Error) + Send + Sync>);
+
+impl fmt::Debug for SkipHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SkipHook")
+    }
+}
+
+/// An input file whose header has been read, and the folder it was found in.
+struct InputFile<'a> {
+    /// The position of the folder in the resolved folders.
+    position: usize,
+    folder: &'a str,
+    path: PathBuf,
+    dialect: Dialect,
+    /// The input file's header, or its first row if it doesn't have a header.
+    header: csv::ByteRecord,
+}
+
+/// A summary of a completed aggregation.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The folders whose input files were aggregated, in order.
+    pub folders: Vec<String>,
+    /// The errors for folders that were skipped because of the error mode, in
+    /// order.
+    pub skipped: Vec<Error>,
+    /// The number of rows written (excluding the header).
+    pub rows: u64,
+}
+
+impl Aggregator {
+    /// Creates an aggregator that looks for an input file with the provided
+    /// name in each folder.
+    ///
+    /// By default, the root directory is the current working directory, the
+    /// error mode is `Fail`, the first input file's header is used, fields are
+    /// only quoted when necessary, and input files are read concurrently (using
+    /// every available CPU) with rows written in order.
+    pub fn new(filename: impl Into<String>) -> Self {
+        Aggregator {
+            filename: filename.into(),
+            root: PathBuf::from("./"),
+            folders: Vec::new(),
+            globs: Vec::new(),
+            recursive: false,
+            column: None,
+            partitions: false,
+            pattern: None,
+            input_encoding: None,
+            error_mode: ErrorMode::Fail,
+            on_skip: None,
+            header_mode: HeaderMode::First,
+            delimiter: b',',
+            sniff: false,
+            format: Format::Csv,
+            output_delimiter: b',',
+            output_encoding: OutputEncoding::Utf8,
+            quote_style: QuoteStyle::Necessary,
+            line_ending: LineEnding::Lf,
+            table: None,
+            append: false,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            ordered: true,
+        }
+    }
+
+    /// Sets the root directory of the input folders.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Adds folders (relative to the root directory) to look in for the input
+    /// file.
+    pub fn folders<I, S>(mut self, folders: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.folders.extend(folders.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds a glob pattern (relative to the root directory) matching folders to
+    /// look in for the input file, e.g. `2024-*` or `region/*/daily`.
+    pub fn glob(mut self, pattern: impl Into<String>) -> Self {
+        self.globs.push(pattern.into());
+        self
+    }
+
+    /// Sets whether every folder under the root directory that contains the
+    /// input file should be found recursively.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Sets the name of the column containing the path of the folder that each
+    /// row originated from.
+    pub fn column(mut self, column: impl Into<String>) -> Self {
+        self.column = Some(column.into());
+        self
+    }
+
+    /// Sets whether Hive-style `key=value` segments of the folders' paths
+    /// should be added as columns, with a column for each key.
+    pub fn partitions(mut self, partitions: bool) -> Self {
+        self.partitions = partitions;
+        self
+    }
+
+    /// Sets a regular expression that is applied to each folder's path, adding
+    /// a column for each of its named capture groups.
+    pub fn pattern(mut self, pattern: Regex) -> Self {
+        self.pattern = Some(pattern);
+        self
+    }
+
+    /// Sets the encoding of the input files, which are transcoded to UTF-8.
+    ///
+    /// A byte order mark (BOM) at the start of an input file takes precedence
+    /// over this encoding. By default, input files without a BOM are assumed to
+    /// be UTF-8.
+    pub fn input_encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.input_encoding = Some(encoding);
+        self
+    }
+
+    /// Sets the delimiter that separates fields in the input files. By default,
+    /// fields are separated by commas.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets whether the dialect of each input file (its delimiter, quote
+    /// character and whether it has a header) should be sniffed from its
+    /// first few kilobytes, rather than assumed.
+    ///
+    /// The delimiter set with `delimiter` is used for input files whose
+    /// delimiter can't be sniffed. Rows from input files without a header are
+    /// assumed to have the output header's columns, in order. Every input file
+    /// is written in the same output dialect.
+    pub fn sniff(mut self, sniff: bool) -> Self {
+        self.sniff = sniff;
+        self
+    }
+
+    /// Sets the format that the output is written in. By default, the output
+    /// is written as CSV.
+    ///
+    /// The delimiter and quote style only apply to CSV output. JSON output
+    /// has an object for each row (keyed by the header's names, including the
+    /// source columns) with every value as a string.
+    ///
+    /// Parquet and Arrow output have a schema inferred from every row's values,
+    /// with empty values as nulls (except in string columns) and the source
+    /// columns dictionary-encoded. As the schema isn't known until every row has been
+    /// read, rows are spooled to a temporary file before being converted.
+    ///
+    /// JSON, Parquet and Arrow output can only contain Unicode text, so rather
+    /// than replacing bytes that aren't valid UTF-8, an error naming the folder
+    /// and record is returned for them (which is collected, if the error mode
+    /// is `Collect`). CSV output passes them through untouched (unless it's
+    /// written as UTF-16LE, see `output_encoding`), while SQLite output stores
+    /// the fields as blobs.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the delimiter that separates fields in the output (including the
+    /// source columns). By default, fields are separated by commas.
+    ///
+    /// Fields are re-quoted for the output delimiter, so fields containing it
+    /// are quoted even if they weren't quoted in the input files.
+    pub fn output_delimiter(mut self, delimiter: u8) -> Self {
+        self.output_delimiter = delimiter;
+        self
+    }
+
+    /// Sets the text encoding that the output is written in, which must match
+    /// the encoding of the sink passed to `run` (e.g. a [`Transcoder`]). By
+    /// default, the output is UTF-8.
+    ///
+    /// UTF-16LE output can only contain Unicode text, so rather than replacing
+    /// bytes that aren't valid UTF-8, an error naming the folder and record is
+    /// returned for them (as for JSON, Parquet and Arrow output).
+    ///
+    /// [`Transcoder`]: crate::encoding::Transcoder
+    pub fn output_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.output_encoding = encoding;
+        self
+    }
+
+    /// Sets the behaviour when an input file is not found in a folder (or a
+    /// folder doesn't match the pattern).
+    pub fn error_mode(mut self, mode: ErrorMode) -> Self {
+        self.error_mode = mode;
+        self
+    }
+
+    /// Sets a function that's called with the error for each folder that's
+    /// skipped because of the error mode, as soon as it's skipped.
+    ///
+    /// Skipped folders are also listed in the summary, but only once the
+    /// aggregation has succeeded, so this allows them to be reported even if
+    /// it later fails.
+    pub fn on_skip(mut self, hook: impl Fn(&Error) + Send + Sync + 'static) -> Self {
+        self.on_skip = Some(SkipHook(Arc::new(hook)));
+        self
+    }
+
+    /// Sets how the headers of the input files are reconciled.
+    pub fn header_mode(mut self, mode: HeaderMode) -> Self {
+        self.header_mode = mode;
+        self
+    }
+
+    /// Sets when fields in the output are quoted.
+    pub fn quote_style(mut self, style: QuoteStyle) -> Self {
+        self.quote_style = style;
+        self
+    }
+
+    /// Sets the line ending that terminates each record in the output
+    /// (including the header). By default, records end with a line feed.
+    pub fn line_ending(mut self, ending: LineEnding) -> Self {
+        self.line_ending = ending;
+        self
+    }
+
+    /// Sets the name of the table that rows are inserted into, for SQLite
+    /// output. By default, a new table is created for each run.
+    pub fn table(mut self, table: impl Into<String>) -> Self {
+        self.table = Some(table.into());
+        self
+    }
+
+    /// Sets whether rows are appended to the table if it already exists, for
+    /// SQLite output, matching the output's columns to the table's columns by
+    /// name.
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Sets the number of threads used to read the input files concurrently.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets whether rows are written in the order of the input folders, rather
+    /// than as soon as they're read.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Resolves the folders to look in for the input file.
+    ///
+    /// Folders added with `folders` come first (in their original order),
+    /// followed by any folders discovered with `glob` or `recursive` (sorted by
+    /// path). Folders that would be included more than once are only included
+    /// the first time.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if a glob pattern is invalid, or if a
+    /// directory can't be read while discovering folders.
+    pub fn resolve_folders(&self) -> Result<Vec<String>> {
+        let mut discovered = Vec::new();
+        for pattern in &self.globs {
+            discovered.extend(discovery::glob(&self.root, pattern)?);
+        }
+        if self.recursive {
+            discovered.extend(discovery::recursive(&self.root, &self.filename)?);
+        }
+        discovered.sort();
+
+        let mut folders: Vec<String> = Vec::new();
+        for folder in self.folders.iter().cloned().chain(discovered) {
+            if !folders.contains(&folder) {
+                folders.push(folder);
+            }
+        }
+        Ok(folders)
+    }
+
+    /// Aggregates the input files, writing the output CSV data to the provided
+    /// sink, and returns a summary of the aggregation.
+    ///
+    /// Rows are written straight through to the sink as they're read, so
+    /// memory usage stays constant regardless of the size of the input files.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if an input file can't be found or its
+    /// folder doesn't match the pattern (unless the error mode is `Skip` or
+    /// `Warn`), if an input file can't be read, if headers don't match when
+    /// strictly checked, or if the output can't be written.
+    ///
+    /// If the error mode is `Collect`, missing and unreadable input files don't
+    /// stop the aggregation: every other input file is still aggregated, and
+    /// then an `Error::Collected` listing every failed folder is returned.
+    ///
+    /// SQLite output can't be written to a sink, so an error is also returned
+    /// if the format is `Sqlite` (see `run_sqlite`).
+    pub fn run<W: Write + Send>(&self, sink: W) -> Result<Summary> {
+        self.aggregate(|source_columns| self.writer(sink, source_columns))
+    }
+
+    /// Aggregates the input files, inserting the rows into a table in the
+    /// SQLite database at the provided path (which is created if it doesn't
+    /// exist), and returns a summary of the aggregation.
+    ///
+    /// Rows are inserted into the table set with `table`, or else a new table
+    /// named after the current time (e.g. `run_1700000000`, or
+    /// `run_1700000000_2` if that table already exists). The table is
+    /// created with a text column for each column of the output, and an index
+    /// for each source column. Rows are inserted in batched transactions.
+    ///
+    /// If appending is enabled and the table already exists, the output's
+    /// columns are matched to the table's columns by name instead.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error in the same cases as `run`, if the
+    /// table already exists (unless appending), or if the output has columns
+    /// that aren't in an existing table (when appending).
+    pub fn run_sqlite(&self, path: impl AsRef<Path>) -> Result<Summary> {
+        self.aggregate(|source_columns| {
+            let writer = SqliteWriter::open(
+                path.as_ref(),
+                self.table.clone(),
+                self.append,
+                source_columns,
+            )?;
+            Ok(RecordWriter::<io::Sink>::Sqlite(Box::new(writer)))
+        })
+    }
+
+    /// Aggregates the input files, writing the output records with the writer
+    /// returned by the provided function (which is passed the number of source
+    /// columns).
+    fn aggregate<W, F>(&self, writer: F) -> Result<Summary>
+    where
+        W: Write + Send,
+        F: FnOnce(usize) -> Result<RecordWriter<W>>,
+    {
+        let folders = self.resolve_folders()?;
+        let mut summary = Summary::default();
+        let mut inputs = Vec::new();
+        let mut failures = Vec::new();
+        let mut source = SourceColumns {
+            column: self.column.clone(),
+            partitions: Vec::new(),
+            pattern: self.pattern.clone(),
+        };
+
+        // Read the header of every input file up front, so that the output
+        // header can be reconciled before any rows are read
+        for (position, folder) in folders.iter().enumerate() {
+            match self.read_header(&source, folder) {
+                Ok((path, dialect, header)) => inputs.push(InputFile {
+                    position,
+                    folder,
+                    path,
+                    dialect,
+                    header,
+                }),
+                Err(err) => match self.error_mode {
+                    ErrorMode::Collect => failures.push((position, err)),
+                    ErrorMode::Skip | ErrorMode::Warn if err.is_missing() => {
+                        if let Some(SkipHook(hook)) = &self.on_skip {
+                            hook(&err);
+                        }
+                        summary.skipped.push(err)
+                    }
+                    _ => return Err(err),
+                },
+            }
+        }
+
+        if self.partitions {
+            source.partitions = source::partition_keys(inputs.iter().map(|input| input.folder));
+        }
+
+        // Only input files with a header are reconciled: rows from any other
+        // input file are assumed to have the output header's columns
+        let named: Vec<_> = inputs
+            .iter()
+            .filter(|input| input.dialect.has_headers)
+            .collect();
+
+        // Only write a header if at least one input file was found. If none of
+        // them have a header, columns are named by their position instead
+        let header = inputs.first().map(|first| match named.first() {
+            Some(first) => match self.header_mode {
+                HeaderMode::First | HeaderMode::Strict => first.header.clone(),
+                HeaderMode::Union => header::union(named.iter().map(|input| &input.header)),
+            },
+            None => (1..=first.header.len())
+                .map(|column| format!("column{}", column))
+                .collect(),
+        });
+
+        // Report every mismatched header at once when headers are strictly
+        // checked
+        if let (HeaderMode::Strict, Some(first)) = (self.header_mode, named.first()) {
+            let mismatches: Vec<_> = named
+                .iter()
+                .map(|input| {
+                    let diff = HeaderDiff::new(&first.header, &input.header);
+                    (input.folder.to_owned(), diff)
+                })
+                .filter(|(_, diff)| !diff.is_empty())
+                .collect();
+            if !mismatches.is_empty() {
+                return Err(Error::HeaderMismatch {
+                    folder: first.folder.to_owned(),
+                    mismatches,
+                });
+            }
+        }
+
+        let mut writer = writer(source.header().len())?;
+        if let Some(header) = &header {
+            let mut record = csv::ByteRecord::from(source.header());
+            record.extend(header);
+            writer.write_header(&record)?;
+        }
+
+        let jobs: Vec<_> = inputs
+            .iter()
+            .map(|input| {
+                let mapping = match self.header_mode {
+                    HeaderMode::Union if input.dialect.has_headers => header
+                        .as_ref()
+                        .map(|header| ColumnMap::new(header, &input.header)),
+                    _ => None,
+                };
+                (input, mapping, source.values(input.folder))
+            })
+            .collect();
+
+        // Input files are read and parsed concurrently, while rows are written
+        // (in order, unless unordered) on this thread. When errors are
+        // collected, a folder that fails part-way through is recorded (rather
+        // than stopping the aggregation)
+        let row_failures = Mutex::new(Vec::new());
+        parallel::process(
+            &jobs,
+            self.threads,
+            self.ordered,
+            |(input, mapping, values), emit| match self.read_rows(input, mapping, values, emit) {
+                Err(err) if self.error_mode == ErrorMode::Collect => {
+                    row_failures.lock().unwrap().push((input.position, err));
+                    Ok(())
+                }
+                result => result,
+            },
+            |record| {
+                writer.write_row(&record)?;
+                summary.rows += 1;
+                Ok(())
+            },
+        )?;
+
+        writer.finish()?;
+
+        failures.extend(row_failures.into_inner().unwrap());
+        if !failures.is_empty() {
+            failures.sort_by_key(|(position, _)| *position);
+            let errors = failures.into_iter().map(|(_, err)| err).collect();
+            return Err(Error::Collected(errors));
+        }
+
+        summary.folders = inputs
+            .into_iter()
+            .map(|input| input.folder.to_owned())
+            .collect();
+        Ok(summary)
+    }
+
+    /// Creates the writer for the output format, where the first
+    /// `source_columns` columns of each record are the source columns.
+    fn writer<W: Write + Send>(&self, sink: W, source_columns: usize) -> Result<RecordWriter<W>> {
+        let columnar = |sink, format| {
+            let writer = ColumnarWriter::new(sink, format, source_columns)?;
+            Ok::<_, Error>(RecordWriter::Columnar(Box::new(writer)))
+        };
+        Ok(match self.format {
+            Format::Csv => RecordWriter::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .flexible(true)
+                    .delimiter(self.output_delimiter)
+                    .quote_style(self.quote_style.into())
+                    .terminator(self.line_ending.into())
+                    .from_writer(sink),
+            )),
+            Format::Ndjson | Format::Json => {
+                RecordWriter::Json(JsonWriter::new(sink, self.format, self.line_ending))
+            }
+            Format::Parquet => columnar(sink, Columnar::Parquet)?,
+            Format::Arrow => columnar(sink, Columnar::ArrowFile)?,
+            Format::ArrowStream => columnar(sink, Columnar::ArrowStream)?,
+            Format::Sqlite => {
+                return Err(Error::InvalidArgument(
+                    "SQLite output can only be written to a file".to_owned(),
+                ))
+            }
+        })
+    }
+
+    /// Reads the header of the input file in a folder (or its first row, if it
+    /// doesn't have a header), returning the input file's path and dialect
+    /// alongside it.
+    ///
+    /// The header of a Parquet or Arrow file is the names of its schema's
+    /// fields, while the header of a JSON file is the union of its objects'
+    /// keys (with the keys of nested objects joined with dots, e.g. `a.b`).
+    fn read_header(
+        &self,
+        source: &SourceColumns,
+        folder: &str,
+    ) -> Result<(PathBuf, Dialect, csv::ByteRecord)> {
+        if !source.matches(folder) {
+            return Err(Error::Unmatched {
+                folder: folder.to_owned(),
+            });
+        }
+
+        let path = self.root.join(folder).join(&self.filename);
+        let opened = open_input(&path, self.input_encoding)?.ok_or_else(|| Error::NotFound {
+            folder: folder.to_owned(),
+        })?;
+        let reader = match opened {
+            Opened::Csv(reader) => reader,
+            Opened::Json(reader) => {
+                let header = json::header(reader).map_err(|err| Error::json(folder, &path, err))?;
+                return Ok((path, Dialect::new(self.delimiter), header));
+            }
+            opened => {
+                let (schema, _) =
+                    columnar::batches(opened).map_err(|err| Error::arrow(folder, &path, err))?;
+                let header = schema.fields().iter().map(|field| field.name()).collect();
+                return Ok((path, Dialect::new(self.delimiter), header));
+            }
+        };
+        let (dialect, reader) = if self.sniff {
+            let (sample, reader) =
+                dialect::sample(reader).map_err(|err| Error::read(&path, err))?;
+            (
+                Dialect::sniff(&sample, Dialect::new(self.delimiter)),
+                reader,
+            )
+        } else {
+            (Dialect::new(self.delimiter), reader)
+        };
+        let header = csv_reader(reader, &dialect)
+            .byte_headers()
+            .map_err(|err| Error::csv(folder, &path, err))?
+            .clone();
+        self.check_utf8(folder, 1, &header)?;
+        Ok((path, dialect, header))
+    }
+
+    /// Reads the rows of the input file in a folder, emitting each of them as
+    /// an output record (re-ordered by the column mapping, if any, and
+    /// prefixed with the folder's source column values).
+    ///
+    /// Rows are read as raw bytes, so content that isn't valid UTF-8 is passed
+    /// through to the output untouched (rather than being dropped). The values
+    /// of Parquet and Arrow files are formatted as text, and JSON objects are
+    /// flattened into the fields of the file's header.
+    fn read_rows(
+        &self,
+        input: &InputFile,
+        mapping: &Option<ColumnMap>,
+        values: &[String],
+        emit: &mut dyn FnMut(csv::ByteRecord) -> bool,
+    ) -> Result<()> {
+        let (folder, path) = (input.folder, &input.path);
+        let opened = open_input(path, self.input_encoding)?.ok_or_else(|| Error::NotFound {
+            folder: folder.to_owned(),
+        })?;
+        let rows: Box<dyn Iterator<Item = Result<csv::ByteRecord>>> = match opened {
+            Opened::Csv(reader) => Box::new(
+                csv_reader(reader, &input.dialect)
+                    .into_byte_records()
+                    .map(|row| row.map_err(|err| Error::csv(folder, path, err))),
+            ),
+            Opened::Json(reader) => Box::new(
+                json::rows(reader, &input.header)
+                    .map(|row| row.map_err(|err| Error::json(folder, path, err))),
+            ),
+            opened => {
+                let (_, batches) =
+                    columnar::batches(opened).map_err(|err| Error::arrow(folder, path, err))?;
+                Box::new(batches.flat_map(|batch| {
+                    match batch.and_then(|batch| columnar::rows(&batch)) {
+                        Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+                        Err(err) => vec![Err(Error::arrow(folder, path, err))],
+                    }
+                }))
+            }
+        };
+
+        // Records are counted from 1, including the header
+        let first = if input.dialect.has_headers { 2 } else { 1 };
+        for (record, row) in (first..).zip(rows) {
+            let row = row?;
+            self.check_utf8(folder, record, &row)?;
+            let row = match mapping {
+                Some(mapping) => mapping.apply(&row),
+                None => row,
+            };
+            let mut record = csv::ByteRecord::from(values);
+            record.extend(&row);
+            if !emit(record) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a record from the input file in a folder is valid UTF-8, if
+    /// the output format (or encoding) can only contain Unicode text.
+    fn check_utf8(&self, folder: &str, record: u64, row: &csv::ByteRecord) -> Result<()> {
+        let output = if self.format.requires_utf8() {
+            format!("{:?}", self.format)
+        } else if self.output_encoding.requires_utf8() {
+            "UTF-16LE".to_owned()
+        } else {
+            return Ok(());
+        };
+        if std::str::from_utf8(row.as_slice()).is_ok() {
+            return Ok(());
+        }
+        Err(Error::InvalidUtf8 {
+            folder: folder.to_owned(),
+            record,
+            output,
+        })
+    }
+}
@@ -0,0 +1,549 @@
+use crate::{Error, Opened, Result};
+use arrow_array::types::Int32Type;
+use arrow_array::{
+    ArrayRef, BooleanArray, Date32Array, DictionaryArray, Float64Array, Int32Array, Int64Array,
+    RecordBatch, RecordBatchReader, StringArray,
+};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_ipc::reader::{FileReader, StreamReader};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use csv::ByteRecord;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Seek, Write};
+use std::sync::Arc;
+
+/// The number of rows in each record batch read from or written to a columnar
+/// format.
+const BATCH_SIZE: usize = 8192;
+
+/// Different columnar formats that the aggregated rows can be written in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Columnar {
+    /// Rows are written as an Apache Parquet file.
+    Parquet,
+    /// Rows are written as an Arrow IPC file (also known as Feather V2).
+    ArrowFile,
+    /// Rows are written as an Arrow IPC stream.
+    ArrowStream,
+}
+
+/// Different types that a column's values can be inferred as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ColumnType {
+    Boolean,
+    Integer,
+    Float,
+    Date,
+    String,
+}
+
+impl ColumnType {
+    /// Infers the type of a (non-empty) value.
+    fn infer(value: &[u8]) -> Self {
+        let Ok(value) = std::str::from_utf8(value) else {
+            return ColumnType::String;
+        };
+        // Zero-padded numbers (e.g. zip codes) would lose their padding as
+        // numbers, and integers too large for an `i64` would lose precision
+        // as floats, so both are kept as strings
+        if is_zero_padded(value) || (is_integer(value) && value.parse::<i64>().is_err()) {
+            ColumnType::String
+        } else if value.parse::<i64>().is_ok() {
+            ColumnType::Integer
+        } else if parse_float(value).is_some() {
+            ColumnType::Float
+        } else if parse_bool(value).is_some() {
+            ColumnType::Boolean
+        } else if parse_date(value).is_some() {
+            ColumnType::Date
+        } else {
+            ColumnType::String
+        }
+    }
+
+    /// Returns the narrowest type that both types' values can be stored as.
+    fn merge(self, other: ColumnType) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => {
+                ColumnType::Float
+            }
+            _ => ColumnType::String,
+        }
+    }
+
+    /// The Arrow data type for the column's values.
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Integer => DataType::Int64,
+            ColumnType::Float => DataType::Float64,
+            ColumnType::Date => DataType::Date32,
+            ColumnType::String => DataType::Utf8,
+        }
+    }
+}
+
+/// The distinct values of a source column, each with the key that it's encoded
+/// as in the column's dictionary.
+///
+/// A single dictionary is shared by every record batch, as some formats (e.g.
+/// Arrow IPC files) don't support replacing a column's dictionary.
+#[derive(Debug, Clone, Default)]
+struct Dictionary {
+    keys: HashMap<Vec<u8>, i32>,
+}
+
+impl Dictionary {
+    /// Adds a value to the dictionary, if it isn't already included.
+    fn insert(&mut self, value: &[u8]) {
+        if !self.keys.contains_key(value) {
+            let key = self.keys.len() as i32;
+            self.keys.insert(value.to_vec(), key);
+        }
+    }
+
+    /// The dictionary's values, in the order of their keys.
+    fn values(&self) -> ArrayRef {
+        let mut values: Vec<_> = self.keys.iter().collect();
+        values.sort_by_key(|(_, key)| **key);
+        Arc::new(
+            values
+                .into_iter()
+                .map(|(value, _)| Some(String::from_utf8_lossy(value)))
+                .collect::<StringArray>(),
+        )
+    }
+}
+
+/// Writes the aggregated records in a columnar format, with a schema inferred
+/// from every row.
+///
+/// As the schema isn't known until every row has been seen, rows are spooled
+/// to a temporary file (rather than held in memory) while their types are
+/// inferred, and then converted once the aggregation has finished.
+pub(crate) struct ColumnarWriter<W: Write + Send> {
+    sink: W,
+    format: Columnar,
+    /// The number of source columns at the start of each row, which are
+    /// dictionary-encoded rather than inferred.
+    source_columns: usize,
+    header: Vec<String>,
+    /// The inferred type of each column, or `None` if every value so far has
+    /// been empty.
+    types: Vec<Option<ColumnType>>,
+    dictionaries: Vec<Dictionary>,
+    spool: csv::Writer<File>,
+}
+
+impl<W: Write + Send> ColumnarWriter<W> {
+    /// Creates a writer for a columnar format, where the first
+    /// `source_columns` columns of each row are the source columns.
+    pub fn new(sink: W, format: Columnar, source_columns: usize) -> Result<Self> {
+        let spool = tempfile::tempfile().map_err(Error::Write)?;
+        Ok(ColumnarWriter {
+            sink,
+            format,
+            source_columns,
+            header: Vec::new(),
+            types: Vec::new(),
+            dictionaries: vec![Dictionary::default(); source_columns],
+            spool: csv::WriterBuilder::new().flexible(true).from_writer(spool),
+        })
+    }
+
+    /// Sets the header, which names the columns in the schema.
+    pub fn write_header(&mut self, header: &ByteRecord) {
+        self.header = header
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+        self.types = vec![None; self.header.len()];
+    }
+
+    /// Spools a row, updating the inferred type of each of its columns (or
+    /// the dictionary of each source column). Fields beyond the end of the
+    /// header are dropped.
+    pub fn write_row(&mut self, row: &ByteRecord) -> Result<()> {
+        for (dictionary, value) in self.dictionaries.iter_mut().zip(row) {
+            dictionary.insert(value);
+        }
+        let columns = self.types.iter_mut().zip(row).skip(self.source_columns);
+        for (column, value) in columns.filter(|(_, value)| !value.is_empty()) {
+            let inferred = ColumnType::infer(value);
+            *column = Some(column.map_or(inferred, |column| column.merge(inferred)));
+        }
+        Ok(self.spool.write_byte_record(row)?)
+    }
+
+    /// Writes every spooled row in the columnar format, and flushes the
+    /// output.
+    pub fn finish(self) -> Result<()> {
+        let schema = self.schema();
+        let mut spool = self
+            .spool
+            .into_inner()
+            .map_err(|err| Error::Write(err.into_error()))?;
+        spool.rewind().map_err(Error::Write)?;
+        let mut rows = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(spool)
+            .into_byte_records();
+
+        let dictionaries: Vec<_> = self
+            .dictionaries
+            .iter()
+            .map(|dictionary| (dictionary, dictionary.values()))
+            .collect();
+        let mut writer = BatchWriter::new(self.sink, self.format, schema.clone())?;
+        loop {
+            let batch = rows
+                .by_ref()
+                .take(BATCH_SIZE)
+                .collect::<csv::Result<Vec<_>>>()?;
+            if batch.is_empty() {
+                break;
+            }
+            writer.write(&record_batch(&schema, &dictionaries, &batch)?)?;
+        }
+        writer.finish()
+    }
+
+    /// The schema of the output: source columns are dictionary-encoded
+    /// strings, while every other column has its inferred type (or is a
+    /// string, if every value was empty).
+    fn schema(&self) -> SchemaRef {
+        let fields = self.header.iter().zip(&self.types).enumerate();
+        let fields: Vec<_> = fields
+            .map(|(index, (name, column))| match column {
+                _ if index < self.source_columns => Field::new(
+                    name,
+                    DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                    false,
+                ),
+                column => Field::new(name, column.unwrap_or(ColumnType::String).data_type(), true),
+            })
+            .collect();
+        Arc::new(Schema::new(fields))
+    }
+}
+
+/// Converts spooled rows to a record batch with the provided schema, encoding
+/// the source columns with their dictionaries (and their values).
+fn record_batch(
+    schema: &SchemaRef,
+    dictionaries: &[(&Dictionary, ArrayRef)],
+    rows: &[ByteRecord],
+) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let values = rows.iter().map(|row| row.get(index));
+            match dictionaries.get(index) {
+                Some((dictionary, dictionary_values)) => {
+                    let keys: Int32Array = values
+                        .map(|value| dictionary.keys.get(value.unwrap_or_default()).copied())
+                        .collect();
+                    let array = DictionaryArray::<Int32Type>::new(keys, dictionary_values.clone());
+                    Arc::new(array) as ArrayRef
+                }
+                None => column(field.data_type(), values),
+            }
+        })
+        .collect();
+    RecordBatch::try_new(schema.clone(), columns).map_err(write_error)
+}
+
+/// Converts a column's values to an array of the column's type. Values that
+/// are empty (or missing) are null, except in string columns where only
+/// missing values are null.
+///
+/// Values are checked to be valid UTF-8 as they're read, so converting them to
+/// strings never replaces any bytes.
+fn column<'a>(data_type: &DataType, values: impl Iterator<Item = Option<&'a [u8]>>) -> ArrayRef {
+    let text = |value: &[u8]| String::from_utf8_lossy(value).into_owned();
+    let typed = |value: Option<&'a [u8]>| {
+        value
+            .filter(|value| !value.is_empty())
+            .and_then(|value| std::str::from_utf8(value).ok())
+    };
+    match data_type {
+        DataType::Boolean => Arc::new(
+            values
+                .map(|value| typed(value).and_then(parse_bool))
+                .collect::<BooleanArray>(),
+        ),
+        DataType::Int64 => Arc::new(
+            values
+                .map(|value| typed(value).and_then(|value| value.parse().ok()))
+                .collect::<Int64Array>(),
+        ),
+        DataType::Float64 => Arc::new(
+            values
+                .map(|value| typed(value).and_then(parse_float))
+                .collect::<Float64Array>(),
+        ),
+        DataType::Date32 => Arc::new(
+            values
+                .map(|value| typed(value).and_then(parse_date))
+                .collect::<Date32Array>(),
+        ),
+        _ => Arc::new(values.map(|value| value.map(text)).collect::<StringArray>()),
+    }
+}
+
+/// Writes record batches to a sink in a columnar format.
+enum BatchWriter<W: Write + Send> {
+    Parquet(parquet::arrow::ArrowWriter<W>),
+    ArrowFile(arrow_ipc::writer::FileWriter<W>),
+    ArrowStream(arrow_ipc::writer::StreamWriter<W>),
+}
+
+impl<W: Write + Send> BatchWriter<W> {
+    fn new(sink: W, format: Columnar, schema: SchemaRef) -> Result<Self> {
+        match format {
+            Columnar::Parquet => {
+                let properties = parquet::file::properties::WriterProperties::builder()
+                    .set_compression(parquet::basic::Compression::SNAPPY)
+                    .build();
+                parquet::arrow::ArrowWriter::try_new(sink, schema, Some(properties))
+                    .map(BatchWriter::Parquet)
+                    .map_err(write_error)
+            }
+            Columnar::ArrowFile => arrow_ipc::writer::FileWriter::try_new(sink, &schema)
+                .map(BatchWriter::ArrowFile)
+                .map_err(write_error),
+            Columnar::ArrowStream => arrow_ipc::writer::StreamWriter::try_new(sink, &schema)
+                .map(BatchWriter::ArrowStream)
+                .map_err(write_error),
+        }
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            BatchWriter::Parquet(writer) => writer.write(batch).map_err(write_error),
+            BatchWriter::ArrowFile(writer) => writer.write(batch).map_err(write_error),
+            BatchWriter::ArrowStream(writer) => writer.write(batch).map_err(write_error),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        let mut sink = match self {
+            BatchWriter::Parquet(writer) => writer.into_inner().map_err(write_error)?,
+            BatchWriter::ArrowFile(writer) => writer.into_inner().map_err(write_error)?,
+            BatchWriter::ArrowStream(writer) => writer.into_inner().map_err(write_error)?,
+        };
+        sink.flush().map_err(Error::Write)
+    }
+}
+
+/// The record batches of a Parquet or Arrow input file.
+pub(crate) type Batches =
+    Box<dyn Iterator<Item = std::result::Result<RecordBatch, ArrowError>> + Send>;
+
+/// Opens the record batches of a Parquet or Arrow input file, returning its
+/// schema alongside them.
+///
+/// # Panics
+///
+/// The function panics if the input file is a CSV file.
+pub(crate) fn batches(opened: Opened) -> std::result::Result<(SchemaRef, Batches), ArrowError> {
+    Ok(match opened {
+        Opened::Parquet(file) => {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file)?
+                .with_batch_size(BATCH_SIZE)
+                .build()?;
+            (reader.schema(), Box::new(reader))
+        }
+        Opened::Arrow(file) => {
+            let reader = FileReader::try_new(file, None)?;
+            (reader.schema(), Box::new(reader))
+        }
+        Opened::ArrowStream(reader) => {
+            let reader = StreamReader::try_new(reader, None)?;
+            (reader.schema(), Box::new(reader))
+        }
+        Opened::Csv(_) | Opened::Json(_) => {
+            unreachable!("CSV and JSON files don't have record batches")
+        }
+    })
+}
+
+/// Converts a record batch to rows, with each value formatted as text (e.g.
+/// dates as `2024-01-31`) and nulls as empty fields.
+pub(crate) fn rows(batch: &RecordBatch) -> std::result::Result<Vec<ByteRecord>, ArrowError> {
+    let options = FormatOptions::default().with_null("");
+    let formatters = batch
+        .columns()
+        .iter()
+        .map(|column| ArrayFormatter::try_new(column, &options))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut field = String::new();
+    (0..batch.num_rows())
+        .map(|index| {
+            let mut row = ByteRecord::new();
+            for formatter in &formatters {
+                field.clear();
+                write!(field, "{}", formatter.value(index))
+                    .map_err(|err| ArrowError::ExternalError(Box::new(err)))?;
+                row.push_field(field.as_bytes());
+            }
+            Ok(row)
+        })
+        .collect()
+}
+
+/// Converts an error from a columnar format's writer into a write error.
+fn write_error(err: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::Write(io::Error::other(err))
+}
+
+/// Parses a boolean, ignoring case (e.g. `true` or `FALSE`).
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Parses a decimal number, excluding special values (e.g. `inf` or `NaN`)
+/// that are more likely to be text, and numbers too large to be stored (e.g.
+/// `1e400`), which would otherwise become infinite.
+fn parse_float(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|float: &f64| float.is_finite())
+        .filter(|_| value.bytes().any(|byte| byte.is_ascii_digit()))
+}
+
+/// Returns `true` if a value is made up of digits, with an optional sign.
+fn is_integer(value: &str) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// Returns `true` if a value is a zero-padded number (e.g. `007` or `-01.5`),
+/// but not if it's a single zero before a decimal point (e.g. `0` or `0.5`).
+fn is_zero_padded(value: &str) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value).as_bytes();
+    matches!(digits, [b'0', next, ..] if next.is_ascii_digit())
+}
+
+/// Parses an ISO 8601 date (e.g. `2024-01-31`) as the number of days since the
+/// Unix epoch.
+fn parse_date(value: &str) -> Option<i32> {
+    let (year, rest) = value.split_once('-')?;
+    let (month, day) = rest.split_once('-')?;
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if !(digits(year) && digits(month) && digits(day)) {
+        return None;
+    }
+
+    let (year, month, day): (i64, i64, i64) =
+        (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    // Days from the civil calendar, counting years from March so that leap
+    // days fall at the end of each year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    i32::try_from(era * 146_097 + day_of_era - 719_468).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infer_keeps_zero_padded_numbers_as_strings() {
+        for value in ["00501", "02134", "-007", "+01", "00.5"] {
+            assert_eq!(ColumnType::infer(value.as_bytes()), ColumnType::String);
+        }
+        assert_eq!(ColumnType::infer(b"0"), ColumnType::Integer);
+        assert_eq!(ColumnType::infer(b"-0"), ColumnType::Integer);
+        assert_eq!(ColumnType::infer(b"501"), ColumnType::Integer);
+        assert_eq!(ColumnType::infer(b"0.5"), ColumnType::Float);
+        assert_eq!(ColumnType::infer(b"-0.25"), ColumnType::Float);
+    }
+
+    #[test]
+    fn infer_keeps_numbers_that_overflow_as_strings() {
+        for value in [
+            "12345678901234567890",
+            "-9223372036854775809",
+            "1e400",
+            "-1e400",
+        ] {
+            assert_eq!(ColumnType::infer(value.as_bytes()), ColumnType::String);
+        }
+        assert_eq!(
+            ColumnType::infer(b"9223372036854775807"),
+            ColumnType::Integer
+        );
+        assert_eq!(ColumnType::infer(b"1e300"), ColumnType::Float);
+    }
+
+    #[test]
+    fn parse_date_counts_days_from_the_epoch() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("1969-12-31"), Some(-1));
+        assert_eq!(parse_date("2000-03-01"), Some(11017));
+        assert_eq!(parse_date("2024-02-29"), Some(19782));
+    }
+
+    #[test]
+    fn parse_date_rejects_invalid_dates() {
+        for value in [
+            "2023-02-29",
+            "1900-02-29",
+            "2024-13-01",
+            "2024-04-31",
+            "2024-00-10",
+            "2024-1-01",
+            "24-01-01",
+            "2024-01-01T00:00",
+            "+024-01-01",
+        ] {
+            assert_eq!(parse_date(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn merge_widens_integers_to_floats_and_anything_else_to_strings() {
+        use ColumnType::*;
+        assert_eq!(Integer.merge(Integer), Integer);
+        assert_eq!(Integer.merge(Float), Float);
+        assert_eq!(Float.merge(Integer), Float);
+        assert_eq!(Date.merge(Date), Date);
+        assert_eq!(Integer.merge(Boolean), String);
+        assert_eq!(Date.merge(Float), String);
+        assert_eq!(String.merge(Integer), String);
+    }
+}
@@ -0,0 +1,217 @@
+use clap::ValueEnum;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Different compression formats that input and output files may be
+/// compressed with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Compression {
+    /// The file isn't compressed.
+    None,
+    /// The file is compressed with gzip.
+    Gzip,
+    /// The file is compressed with Zstandard.
+    Zstd,
+    /// The file is compressed with bzip2.
+    Bzip2,
+    /// The file is compressed with xz (LZMA2).
+    Xz,
+}
+
+impl Compression {
+    /// Every compression format, excluding `None`.
+    pub const ALL: [Compression; 4] = [
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Bzip2,
+        Compression::Xz,
+    ];
+
+    /// Detects the compression format of a file from its first few bytes (its
+    /// "magic bytes"), rather than from its extension.
+    pub fn detect(magic: &[u8]) -> Self {
+        match magic {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+
+    /// The extension conventionally used for files compressed with this format
+    /// (without a leading `.`).
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+            Compression::Bzip2 => Some("bz2"),
+            Compression::Xz => Some("xz"),
+        }
+    }
+
+    /// Detects the compression format of a file from its extension (e.g.
+    /// `output.csv.gz`), for files that haven't been written yet.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(OsStr::to_str);
+        Compression::ALL
+            .into_iter()
+            .find(|compression| compression.extension() == extension)
+            .unwrap_or(Compression::None)
+    }
+
+    /// Validates a compression level for this format, returning the level to
+    /// use (the format's default level, if none is provided).
+    ///
+    /// An error is returned if the level isn't supported by the format, or if
+    /// a level is provided when the output isn't compressed.
+    pub fn validate(self, level: Option<i32>) -> io::Result<i32> {
+        let (levels, default) = self.levels();
+        match level {
+            Some(_) if self == Compression::None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a compression level can't be set when the output isn't compressed",
+            )),
+            Some(level) if !levels.contains(&level) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "compression level {} isn't in the range {}..={} supported by {:?}",
+                    level,
+                    levels.start(),
+                    levels.end(),
+                    self
+                ),
+            )),
+            level => Ok(level.unwrap_or(default)),
+        }
+    }
+
+    /// The range of compression levels supported by this format, and the
+    /// default level used when none is provided.
+    fn levels(self) -> (RangeInclusive<i32>, i32) {
+        match self {
+            Compression::None => (0..=0, 0),
+            Compression::Gzip => (0..=9, 6),
+            Compression::Zstd => (-7..=22, zstd::DEFAULT_COMPRESSION_LEVEL),
+            Compression::Bzip2 => (1..=9, 6),
+            Compression::Xz => (0..=9, 6),
+        }
+    }
+}
+
+/// Returns the provided file name, followed by each of its compressed variants
+/// (e.g. `data.csv`, `data.csv.gz`, `data.csv.zst`, etc.).
+pub fn variants(name: &OsStr) -> Vec<OsString> {
+    let compressed = Compression::ALL
+        .iter()
+        .filter_map(|compression| compression.extension())
+        .map(|extension| {
+            let mut variant = name.to_owned();
+            variant.push(".");
+            variant.push(extension);
+            variant
+        });
+    std::iter::once(name.to_owned()).chain(compressed).collect()
+}
+
+/// Wraps a reader so that its contents are transparently decompressed, if the
+/// reader's magic bytes indicate that it's compressed.
+///
+/// Concatenated (multi-member/multi-stream) gzip, bzip2 and xz files are read
+/// in their entirety.
+pub fn decompress<R>(mut reader: BufReader<R>) -> io::Result<Box<dyn BufRead + Send>>
+where
+    R: io::Read + Send + 'static,
+{
+    let decoder: Box<dyn io::Read + Send> = match Compression::detect(reader.fill_buf()?) {
+        Compression::None => return Ok(Box::new(reader)),
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+        Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+    };
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+/// A writer that transparently compresses everything written to it.
+pub enum Encoder<W: Write> {
+    /// The output isn't compressed.
+    None(W),
+    /// The output is compressed with gzip.
+    Gzip(flate2::write::GzEncoder<W>),
+    /// The output is compressed with Zstandard.
+    Zstd(zstd::Encoder<'static, W>),
+    /// The output is compressed with bzip2.
+    Bzip2(bzip2::write::BzEncoder<W>),
+    /// The output is compressed with xz (LZMA2).
+    Xz(xz2::write::XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Wraps a writer so that everything written to it is compressed with the
+    /// provided format.
+    ///
+    /// If no compression level is provided, the format's default level is
+    /// used. An error is returned if the level is invalid (see
+    /// [`Compression::validate`]).
+    pub fn new(writer: W, compression: Compression, level: Option<i32>) -> io::Result<Self> {
+        let level = compression.validate(level)?;
+
+        // Levels have been validated, so they're never negative for formats
+        // that only support positive levels
+        let unsigned = level as u32;
+        Ok(match compression {
+            Compression::None => Encoder::None(writer),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::new(unsigned),
+            )),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, level)?),
+            Compression::Bzip2 => Encoder::Bzip2(bzip2::write::BzEncoder::new(
+                writer,
+                bzip2::Compression::new(unsigned),
+            )),
+            Compression::Xz => Encoder::Xz(xz2::write::XzEncoder::new(writer, unsigned)),
+        })
+    }
+
+    /// Writes the end of the compressed stream (e.g. its trailer and checksum)
+    /// and returns the underlying writer.
+    ///
+    /// This must be called once everything has been written, as compressed
+    /// streams are otherwise left incomplete (or errors are silently ignored).
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Bzip2(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+        }
+    }
+
+    /// Returns the underlying writer as a trait object, to avoid matching on
+    /// every variant for each write.
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::None(writer) => writer,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+            Encoder::Bzip2(encoder) => encoder,
+            Encoder::Xz(encoder) => encoder,
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
@@ -0,0 +1,205 @@
+use crate::Input;
+use std::io::{self, Cursor, Read};
+
+/// The number of bytes at the start of an input file that are inspected when
+/// sniffing its dialect.
+pub const SAMPLE_SIZE: usize = 8 * 1024;
+
+/// The delimiters that are considered when sniffing a dialect.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// The quote characters that are considered when sniffing a dialect.
+const QUOTES: [u8; 2] = [b'"', b'\''];
+
+/// The format of a CSV file: how its fields are separated and quoted, and
+/// whether its first record is a header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    /// The delimiter that separates fields.
+    pub delimiter: u8,
+    /// The character that fields containing delimiters, quotes or line breaks
+    /// are wrapped in.
+    pub quote: u8,
+    /// Whether the first record is a header, rather than data.
+    pub has_headers: bool,
+}
+
+impl Dialect {
+    /// Creates a dialect with the provided delimiter, double quotes and a
+    /// header.
+    pub fn new(delimiter: u8) -> Self {
+        Dialect {
+            delimiter,
+            quote: b'"',
+            has_headers: true,
+        }
+    }
+
+    /// Infers the dialect of a CSV file from a sample of its first few
+    /// kilobytes, falling back to the provided dialect if no delimiter
+    /// consistently splits the sample into more than one field.
+    ///
+    /// The delimiter that splits the most records into the same number of
+    /// fields is chosen (preferring more fields), and the quote character is
+    /// whichever most often wraps a field. The first record is assumed to be
+    /// a header, unless it looks like data: e.g. if it has a number in a
+    /// column of numbers.
+    pub fn sniff(sample: &[u8], fallback: Dialect) -> Self {
+        // The last line of a full sample is likely to have been cut short
+        let sample = match sample.iter().rposition(|&byte| byte == b'\n') {
+            Some(end) if sample.len() >= SAMPLE_SIZE => &sample[..=end],
+            _ => sample,
+        };
+
+        let Some((delimiter, records)) = DELIMITERS
+            .iter()
+            .map(|&delimiter| (delimiter, parse(sample, delimiter, b'"')))
+            .filter_map(|(delimiter, records)| {
+                let (width, count) = mode(records.iter().map(Vec::len))?;
+                (width > 1).then_some(((count, width), delimiter, records))
+            })
+            .max_by_key(|(score, _, _)| *score)
+            .map(|(_, delimiter, records)| (delimiter, records))
+        else {
+            return fallback;
+        };
+
+        let quote = QUOTES
+            .into_iter()
+            .max_by_key(|&quote| (quoted_fields(sample, delimiter, quote), quote == b'"'))
+            .unwrap_or(b'"');
+        let records = match quote {
+            b'"' => records,
+            quote => parse(sample, delimiter, quote),
+        };
+        Dialect {
+            delimiter,
+            quote,
+            has_headers: has_headers(&records),
+        }
+    }
+}
+
+/// Reads a sample of up to `SAMPLE_SIZE` bytes from the start of an input file,
+/// returning it alongside a reader for the entire file (including the sample).
+pub fn sample(mut reader: Input) -> io::Result<(Vec<u8>, Input)> {
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    (&mut reader)
+        .take(SAMPLE_SIZE as u64)
+        .read_to_end(&mut sample)?;
+    let reader = Box::new(Cursor::new(sample.clone()).chain(reader));
+    Ok((sample, reader))
+}
+
+/// Parses a sample into records, stopping at the first malformed record.
+fn parse(sample: &[u8], delimiter: u8, quote: u8) -> Vec<Vec<Vec<u8>>> {
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .quote(quote)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(sample)
+        .into_byte_records()
+        .map_while(|record| record.ok())
+        .map(|record| record.iter().map(<[u8]>::to_vec).collect())
+        .collect()
+}
+
+/// Returns the most common value (preferring the largest value when tied), and
+/// how many times it occurs.
+fn mode(values: impl Iterator<Item = usize>) -> Option<(usize, usize)> {
+    let mut counts = std::collections::BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(value, count)| (count, value))
+}
+
+/// Counts the fields in a sample that start with a quote character, i.e. the
+/// quote character appears at the start of a line or straight after a
+/// delimiter.
+fn quoted_fields(sample: &[u8], delimiter: u8, quote: u8) -> usize {
+    let starts = std::iter::once(&b'\n').chain(sample);
+    starts
+        .zip(sample)
+        .filter(|&(&previous, &byte)| {
+            byte == quote && (previous == delimiter || previous == b'\n' || previous == b'\r')
+        })
+        .count()
+}
+
+/// Guesses whether the first record is a header, by comparing it against the
+/// other records: each column whose other values are all numbers votes for a
+/// header if the first record's value isn't a number, and against a header if
+/// it is. A header is assumed if the votes are tied.
+fn has_headers(records: &[Vec<Vec<u8>>]) -> bool {
+    let Some((first, rest)) = records.split_first() else {
+        return true;
+    };
+    if rest.is_empty() {
+        return true;
+    }
+
+    let votes: i32 = first
+        .iter()
+        .enumerate()
+        .filter(|&(column, _)| {
+            rest.iter()
+                .filter_map(|record| record.get(column))
+                .all(|value| is_number(value))
+        })
+        .map(|(_, value)| if is_number(value) { -1 } else { 1 })
+        .sum();
+    votes >= 0
+}
+
+/// Returns `true` if a field is a (possibly signed or decimal) number.
+fn is_number(field: &[u8]) -> bool {
+    std::str::from_utf8(field).is_ok_and(|field| field.trim().parse::<f64>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniff(sample: &str) -> Dialect {
+        Dialect::sniff(sample.as_bytes(), Dialect::new(b','))
+    }
+
+    #[test]
+    fn sniff_finds_the_consistent_delimiter() {
+        assert_eq!(sniff("a;b;c\n1;2;3\n4;5;6\n").delimiter, b';');
+        assert_eq!(sniff("a\tb\n1\t2\n").delimiter, b'\t');
+        assert_eq!(sniff("a|b|c\n1|2,5|3\n4|5|6\n").delimiter, b'|');
+    }
+
+    #[test]
+    fn sniff_falls_back_without_a_delimiter() {
+        let fallback = Dialect::new(b':');
+        assert_eq!(Dialect::sniff(b"one\ntwo\n", fallback), fallback);
+    }
+
+    #[test]
+    fn sniff_finds_single_quotes() {
+        let dialect = sniff("name,note\n'a, b',x\n'c',y\n");
+        assert_eq!(dialect.quote, b'\'');
+        assert_eq!(dialect.delimiter, b',');
+    }
+
+    #[test]
+    fn has_headers_votes_with_numeric_columns() {
+        assert!(sniff("id,score\n1,2.5\n2,3\n").has_headers);
+        assert!(!sniff("1,2.5\n2,3\n3,4\n").has_headers);
+        // Text columns don't vote, so the numeric column decides
+        assert!(!sniff("a,1\nb,2\nc,3\n").has_headers);
+        assert!(sniff("name,id\nb,2\nc,3\n").has_headers);
+    }
+
+    #[test]
+    fn has_headers_is_assumed_without_data() {
+        assert!(sniff("a,b\n").has_headers);
+        assert!(sniff("x,y\nfoo,bar\n").has_headers);
+    }
+}
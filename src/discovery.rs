@@ -0,0 +1,78 @@
+use crate::{variants, Error, Result};
+use std::ffi::OsStr;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Finds every folder under the root directory that matches the provided glob
+/// pattern (e.g. `2024-*` or `region/*/daily`).
+///
+/// The matched folders are returned as paths relative to the root directory,
+/// sorted so that the output is deterministic. Files matching the pattern are
+/// ignored.
+///
+/// # Errors
+///
+/// The function will return an error if the pattern is invalid, or if a matched
+/// path can't be read.
+pub fn glob(root: &Path, pattern: &str) -> Result<Vec<String>> {
+    let full = Path::new(&glob::Pattern::escape(&root.to_string_lossy())).join(pattern);
+    let paths = glob::glob(&full.to_string_lossy()).map_err(|err| {
+        Error::InvalidArgument(format!("Invalid glob pattern '{}': {}", pattern, err))
+    })?;
+
+    let mut folders = Vec::new();
+    for path in paths {
+        let path = path.map_err(|err| Error::read(err.path().to_owned(), err.into()))?;
+        if path.is_dir() {
+            folders.push(relative(root, &path));
+        }
+    }
+    folders.sort();
+    Ok(folders)
+}
+
+/// Recursively finds every folder under the root directory (including the root
+/// directory itself) that contains a file with the provided name (or one of
+/// its variants, such as `data.csv.gz` or `data.parquet`).
+///
+/// The found folders are returned as paths relative to the root directory,
+/// sorted so that the output is deterministic.
+///
+/// # Errors
+///
+/// The function will return an error if a directory can't be read during the
+/// traversal.
+pub fn recursive(root: &Path, filename: &str) -> Result<Vec<String>> {
+    let names = variants(OsStr::new(filename));
+    let mut folders = Vec::new();
+    for entry in WalkDir::new(root) {
+        let entry = entry.map_err(|err| {
+            let path = err.path().unwrap_or(root).to_owned();
+            Error::read(path, err.into())
+        })?;
+        if !entry.file_type().is_file() || !names.iter().any(|name| name == entry.file_name()) {
+            continue;
+        }
+        if let Some(folder) = entry.path().parent() {
+            folders.push(relative(root, folder));
+        }
+    }
+    folders.sort();
+    folders.dedup();
+    Ok(folders)
+}
+
+/// Converts a path under the root directory into a path relative to the root,
+/// with `/` separating its components. The root directory itself becomes `.`.
+fn relative(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let components: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    if components.is_empty() {
+        String::from(".")
+    } else {
+        components.join("/")
+    }
+}
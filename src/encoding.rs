@@ -0,0 +1,122 @@
+use crate::{Error, Input, Result};
+use clap::ValueEnum;
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{self, BufReader, Write};
+
+/// Looks up a text encoding by its label (e.g. `utf-8`, `utf-16le`,
+/// `windows-1252` or `latin1`), as defined by the WHATWG Encoding Standard.
+///
+/// # Errors
+///
+/// The function will return an error if the label isn't a known encoding.
+pub fn for_label(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| Error::InvalidArgument(format!("Unknown encoding '{}'", label)))
+}
+
+/// Wraps an input file so that its contents are transcoded to UTF-8.
+///
+/// A byte order mark (BOM) at the start of the file takes precedence over the
+/// provided encoding, and is always stripped (so it doesn't end up glued to
+/// the first column name). If there is no BOM and no encoding is provided, the
+/// file is assumed to be UTF-8.
+///
+/// UTF-8 content is passed through untouched, so bytes that aren't valid UTF-8
+/// are preserved rather than being replaced.
+pub fn decode(reader: Input, encoding: Option<&'static Encoding>) -> Input {
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(encoding)
+        .bom_override(true)
+        .strip_bom(true)
+        .utf8_passthru(true)
+        .build(reader);
+    Box::new(BufReader::new(decoder))
+}
+
+/// Different text encodings that the output file may be written in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputEncoding {
+    /// The output is written as UTF-8, without a byte order mark (BOM).
+    Utf8,
+    /// The output is written as UTF-8, starting with a byte order mark (BOM)
+    /// so that spreadsheet applications (e.g. Excel) detect its encoding.
+    Utf8Bom,
+    /// The output is written as UTF-16 (little-endian), starting with a byte
+    /// order mark (BOM).
+    Utf16le,
+}
+
+impl OutputEncoding {
+    /// Returns `true` if the encoding can only contain Unicode text, so data
+    /// that isn't valid UTF-8 can't be transcoded without replacing bytes.
+    pub fn requires_utf8(self) -> bool {
+        self == OutputEncoding::Utf16le
+    }
+}
+
+/// A writer that transcodes UTF-8 data written to it into an output encoding,
+/// starting with the encoding's byte order mark (BOM) if it has one.
+pub struct Transcoder<W: Write> {
+    writer: W,
+    /// The decoder for the UTF-8 data, if it needs to be transcoded to UTF-16.
+    decoder: Option<Decoder>,
+}
+
+impl<W: Write> Transcoder<W> {
+    /// Wraps a writer so that everything written to it is transcoded into the
+    /// provided encoding, writing the encoding's BOM (if any) immediately.
+    pub fn new(mut writer: W, encoding: OutputEncoding) -> io::Result<Self> {
+        let decoder = match encoding {
+            OutputEncoding::Utf8 => None,
+            OutputEncoding::Utf8Bom => {
+                writer.write_all(b"\xef\xbb\xbf")?;
+                None
+            }
+            OutputEncoding::Utf16le => {
+                writer.write_all(b"\xff\xfe")?;
+                Some(UTF_8.new_decoder_without_bom_handling())
+            }
+        };
+        Ok(Transcoder { writer, decoder })
+    }
+
+    /// Transcodes any incomplete UTF-8 sequence left at the end of the data
+    /// (as a replacement character) and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.transcode(&[], true)?;
+        Ok(self.writer)
+    }
+
+    /// Transcodes UTF-8 data to UTF-16LE, writing it to the underlying writer.
+    /// Bytes that aren't valid UTF-8 are replaced with replacement characters,
+    /// so the aggregator checks that every input record is valid UTF-8 first.
+    fn transcode(&mut self, mut buf: &[u8], last: bool) -> io::Result<()> {
+        let Some(decoder) = &mut self.decoder else {
+            return self.writer.write_all(buf);
+        };
+        let mut units = [0; 4096];
+        let mut bytes = Vec::with_capacity(units.len() * 2);
+        loop {
+            let (result, read, written, _) = decoder.decode_to_utf16(buf, &mut units, last);
+            buf = &buf[read..];
+            bytes.clear();
+            bytes.extend(units[..written].iter().flat_map(|unit| unit.to_le_bytes()));
+            self.writer.write_all(&bytes)?;
+            if result == CoderResult::InputEmpty {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Transcoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transcode(buf, false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,236 @@
+use crate::header::HeaderDiff;
+use arrow_schema::ArrowError;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// A specialized `Result` type for aggregations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Different errors that may be encountered during an aggregation.
+///
+/// Each error belongs to a category with its own exit code (see
+/// [`Error::exit_code`]), so scripts can react to failures without parsing
+/// error messages.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// An argument (e.g. a glob pattern or compression level) is invalid.
+    #[error("{0}")]
+    InvalidArgument(String),
+
+    /// The input file wasn't found in a folder.
+    #[error("Couldn't find file in folder '{folder}'")]
+    NotFound { folder: String },
+
+    /// A folder's path didn't match the pattern.
+    #[error("Folder '{folder}' doesn't match the pattern")]
+    Unmatched { folder: String },
+
+    /// The program doesn't have permission to read or write a file.
+    #[error("Permission denied when trying to access '{}'", path.display())]
+    PermissionDenied { path: PathBuf },
+
+    /// An input file (or a directory being searched for folders) couldn't be
+    /// read.
+    #[error("Encountered an error when reading '{}': {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+
+    /// An input file isn't valid CSV data.
+    #[error("Malformed CSV data in folder '{folder}': {source}")]
+    Malformed { folder: String, source: csv::Error },
+
+    /// A record in an input file isn't valid UTF-8, but the output format (or
+    /// encoding) can only contain Unicode text.
+    #[error(
+        "Record {record} of the input file in folder '{folder}' isn't valid UTF-8, \
+         so it can't be written as {output}"
+    )]
+    InvalidUtf8 {
+        folder: String,
+        /// The position of the record in the input file, counting from 1
+        /// (including the header, if the file has one).
+        record: u64,
+        /// The output format or encoding that the record can't be written in
+        /// (e.g. `Parquet` or `UTF-16LE`).
+        output: String,
+    },
+
+    /// An input file isn't valid JSON data (or contains values that aren't
+    /// objects).
+    #[error("Malformed JSON data in folder '{folder}': {source}")]
+    MalformedJson {
+        folder: String,
+        source: serde_json::Error,
+    },
+
+    /// An input file isn't valid Parquet or Arrow data.
+    #[error("Malformed Parquet or Arrow data in folder '{folder}': {source}")]
+    MalformedArrow { folder: String, source: ArrowError },
+
+    /// The headers of the input files don't match, when strictly checked.
+    #[error(
+        "Headers don't match the header in folder '{folder}':{}",
+        format_mismatches(mismatches)
+    )]
+    HeaderMismatch {
+        /// The folder whose header was expected.
+        folder: String,
+        /// Every other folder whose header differs, with its differences.
+        mismatches: Vec<(String, HeaderDiff)>,
+    },
+
+    /// A SQLite table already exists, when not appending to it.
+    #[error("Table '{table}' already exists (use `--append` to add rows to it)")]
+    TableExists { table: String },
+
+    /// The output's columns don't match the columns of an existing SQLite
+    /// table, when appending to it.
+    #[error("Columns {} aren't in table '{table}'", format_columns(columns))]
+    TableMismatch {
+        /// The table that rows were being appended to.
+        table: String,
+        /// The output's columns that aren't in the table.
+        columns: Vec<String>,
+    },
+
+    /// The output file couldn't be created.
+    #[error("Encountered an error when creating output file '{}': {source}", path.display())]
+    Create { path: PathBuf, source: io::Error },
+
+    /// The output couldn't be written.
+    #[error("Failed to write to output: {0}")]
+    Write(#[source] io::Error),
+
+    /// One or more folders' input files were missing or unreadable, when
+    /// errors are collected.
+    #[error(
+        "{} folder(s) couldn't be aggregated:{}",
+        .0.len(),
+        .0.iter().map(|err| format!("\n  - {}", err)).collect::<String>()
+    )]
+    Collected(Vec<Error>),
+}
+
+impl Error {
+    /// The exit code that the program exits with when it encounters this error.
+    ///
+    /// | Code | Category                                                   |
+    /// |------|------------------------------------------------------------|
+    /// | 2    | Invalid arguments (including an existing SQLite table,     |
+    /// |      | when not appending)                                        |
+    /// | 3    | Missing input (file not found, or folder doesn't match)    |
+    /// | 4    | Permission denied                                          |
+    /// | 5    | Malformed input (invalid CSV, JSON, Parquet or Arrow data, |
+    /// |      | invalid UTF-8 for a Unicode output format or encoding, or  |
+    /// |      | mismatched headers),                                       |
+    /// |      | or a SQLite table that doesn't have the output's columns   |
+    /// | 6    | Read failure                                               |
+    /// | 7    | Write failure (creating or writing the output)             |
+    /// | 8    | One or more folders failed (when errors are collected)     |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) | Error::TableExists { .. } => 2,
+            Error::NotFound { .. } | Error::Unmatched { .. } => 3,
+            Error::PermissionDenied { .. } => 4,
+            Error::Malformed { .. }
+            | Error::InvalidUtf8 { .. }
+            | Error::MalformedJson { .. }
+            | Error::MalformedArrow { .. }
+            | Error::HeaderMismatch { .. }
+            | Error::TableMismatch { .. } => 5,
+            Error::Read { .. } => 6,
+            Error::Create { .. } | Error::Write(_) => 7,
+            Error::Collected(_) => 8,
+        }
+    }
+
+    /// Returns `true` if the error means that an input file was missing (or
+    /// its folder didn't match the pattern), rather than unreadable.
+    pub fn is_missing(&self) -> bool {
+        matches!(self, Error::NotFound { .. } | Error::Unmatched { .. })
+    }
+
+    /// Converts an I/O error encountered while reading a path, distinguishing
+    /// permission errors from other failures.
+    pub(crate) fn read(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path: path.into() },
+            _ => Error::Read {
+                path: path.into(),
+                source,
+            },
+        }
+    }
+
+    /// Converts a CSV error encountered while reading an input file in a
+    /// folder, distinguishing I/O failures from malformed data.
+    pub(crate) fn csv(folder: &str, path: impl Into<PathBuf>, source: csv::Error) -> Self {
+        if !source.is_io_error() {
+            return Error::Malformed {
+                folder: folder.to_owned(),
+                source,
+            };
+        }
+        match source.into_kind() {
+            csv::ErrorKind::Io(source) => Error::read(path, source),
+            // Only I/O errors reach this point
+            _ => unreachable!(),
+        }
+    }
+
+    /// Converts a JSON error encountered while reading an input file in a
+    /// folder, distinguishing I/O failures from malformed data.
+    pub(crate) fn json(folder: &str, path: impl Into<PathBuf>, source: serde_json::Error) -> Self {
+        if source.is_io() {
+            return Error::read(path, source.into());
+        }
+        Error::MalformedJson {
+            folder: folder.to_owned(),
+            source,
+        }
+    }
+
+    /// Converts a Parquet or Arrow error encountered while reading an input
+    /// file in a folder, distinguishing I/O failures from malformed data.
+    pub(crate) fn arrow(folder: &str, path: impl Into<PathBuf>, source: ArrowError) -> Self {
+        match source {
+            ArrowError::IoError(_, source) => Error::read(path, source),
+            source => Error::MalformedArrow {
+                folder: folder.to_owned(),
+                source,
+            },
+        }
+    }
+}
+
+impl From<csv::Error> for Error {
+    /// Converts a CSV error encountered while writing the output.
+    fn from(err: csv::Error) -> Self {
+        if !err.is_io_error() {
+            return Error::Write(io::Error::other(err));
+        }
+        match err.into_kind() {
+            csv::ErrorKind::Io(source) => Error::Write(source),
+            // Only I/O errors reach this point
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Formats a list of column names, e.g. `'a', 'b'`.
+fn format_columns(columns: &[String]) -> String {
+    let columns: Vec<_> = columns
+        .iter()
+        .map(|column| format!("'{}'", column))
+        .collect();
+    columns.join(", ")
+}
+
+/// Formats each folder whose header differs (and its differences) on its own
+/// line.
+fn format_mismatches(mismatches: &[(String, HeaderDiff)]) -> String {
+    mismatches
+        .iter()
+        .map(|(folder, diff)| format!("\n  - folder '{}': {}", folder, diff))
+        .collect()
+}
@@ -0,0 +1,262 @@
+use csv::ByteRecord;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Builds the union of the provided headers, matching columns by name.
+///
+/// Names are compared as raw bytes, so headers that aren't valid UTF-8 are
+/// still matched (and written) exactly as they are.
+///
+/// Columns are ordered by first appearance, so the columns of the first header
+/// come first (in their original order), followed by any new columns from the
+/// second header, and so on.
+///
+/// Duplicate column names are supported: a name appears in the union as many
+/// times as it appears in the header that contains it the most.
+pub fn union<'a, I>(headers: I) -> ByteRecord
+where
+    I: IntoIterator<Item = &'a ByteRecord>,
+{
+    let mut union = ByteRecord::new();
+    let mut counts: HashMap<Vec<u8>, usize> = HashMap::new();
+
+    for header in headers {
+        let mut seen: HashMap<&[u8], usize> = HashMap::new();
+        for name in header {
+            let occurrence = seen.entry(name).or_insert(0);
+            *occurrence += 1;
+            let count = counts.entry(name.to_vec()).or_insert(0);
+            if *occurrence > *count {
+                *count += 1;
+                union.push_field(name);
+            }
+        }
+    }
+
+    union
+}
+
+/// Returns the names that appear more than once in a header (once each, in the
+/// order that they're first repeated).
+///
+/// Names that aren't valid UTF-8 are converted lossily, as they're only used
+/// for reporting.
+pub fn duplicates(header: &ByteRecord) -> Vec<String> {
+    let mut seen = Vec::new();
+    let mut duplicates = Vec::new();
+    for name in header {
+        let name = String::from_utf8_lossy(name);
+        if seen.contains(&name) {
+            if !duplicates.contains(&name) {
+                duplicates.push(name);
+            }
+        } else {
+            seen.push(name);
+        }
+    }
+    duplicates
+        .into_iter()
+        .map(|name| name.into_owned())
+        .collect()
+}
+
+/// A mapping from the columns of an input file to the columns of the output
+/// header, used to re-order rows by column name.
+#[derive(Debug, Clone)]
+pub struct ColumnMap {
+    /// For each output column, the index of the matching input column (if the
+    /// input file has one).
+    indices: Vec<Option<usize>>,
+}
+
+impl ColumnMap {
+    /// Creates a mapping from the `input` header to the `output` header.
+    ///
+    /// The n-th occurrence of a column name in the output header is matched
+    /// with the n-th occurrence of that name in the input header.
+    pub fn new(output: &ByteRecord, input: &ByteRecord) -> Self {
+        let mut positions: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (index, name) in input.iter().enumerate() {
+            positions.entry(name).or_default().push(index);
+        }
+
+        let mut seen: HashMap<&[u8], usize> = HashMap::new();
+        let indices = output
+            .iter()
+            .map(|name| {
+                let occurrence = seen.entry(name).or_insert(0);
+                *occurrence += 1;
+                positions
+                    .get(name)
+                    .and_then(|indices| indices.get(*occurrence - 1))
+                    .copied()
+            })
+            .collect();
+
+        ColumnMap { indices }
+    }
+
+    /// Re-orders a row from the input file so that it lines up with the output
+    /// header.
+    ///
+    /// Columns that are missing from the input file (or from this particular
+    /// row) are left empty, and any fields beyond the input file's header are
+    /// dropped.
+    pub fn apply(&self, row: &ByteRecord) -> ByteRecord {
+        self.indices
+            .iter()
+            .map(|index| index.and_then(|index| row.get(index)).unwrap_or(b""))
+            .collect()
+    }
+}
+
+/// The differences between an input file's header and the expected header,
+/// used to report mismatches when headers are strictly checked.
+///
+/// Column names that aren't valid UTF-8 are converted lossily, as they're only
+/// used for reporting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderDiff {
+    /// Columns that are present in the input file, but not expected.
+    pub added: Vec<String>,
+    /// Columns that are expected, but missing from the input file.
+    pub dropped: Vec<String>,
+    /// Columns that are expected, but have a different name in the same
+    /// position in the input file (as `(expected, actual)` pairs).
+    pub renamed: Vec<(String, String)>,
+    /// Whether the columns shared by both headers appear in a different order.
+    pub reordered: bool,
+}
+
+impl HeaderDiff {
+    /// Compares the `actual` header of an input file with the `expected`
+    /// header.
+    ///
+    /// A column that was dropped from a position, and replaced by a column
+    /// that was added in that same position, is reported as a rename.
+    pub fn new(expected: &ByteRecord, actual: &ByteRecord) -> Self {
+        let is_dropped = |name: &[u8]| !actual.iter().any(|other| other == name);
+        let is_added = |name: &[u8]| !expected.iter().any(|other| other == name);
+        let lossy = |name: &[u8]| String::from_utf8_lossy(name).into_owned();
+
+        let mut diff = HeaderDiff::default();
+        let mut renamed = Vec::new();
+        for (index, name) in expected.iter().enumerate() {
+            if !is_dropped(name) {
+                continue;
+            }
+            match actual.get(index) {
+                Some(other) if is_added(other) => {
+                    renamed.push(other);
+                    diff.renamed.push((lossy(name), lossy(other)));
+                }
+                _ => diff.dropped.push(lossy(name)),
+            }
+        }
+        for name in actual.iter().filter(|name| is_added(name)) {
+            if !renamed.contains(&name) {
+                diff.added.push(lossy(name));
+            }
+        }
+
+        let shared_expected = expected.iter().filter(|name| !is_dropped(name));
+        let shared_actual = actual.iter().filter(|name| !is_added(name));
+        diff.reordered = !shared_expected.eq(shared_actual);
+
+        diff
+    }
+
+    /// Returns `true` if the headers match exactly.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.dropped.is_empty()
+            && self.renamed.is_empty()
+            && !self.reordered
+    }
+}
+
+impl fmt::Display for HeaderDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut changes = Vec::new();
+        if !self.added.is_empty() {
+            changes.push(format!("added {}", self.added.join(", ")));
+        }
+        if !self.dropped.is_empty() {
+            changes.push(format!("dropped {}", self.dropped.join(", ")));
+        }
+        if !self.renamed.is_empty() {
+            let renamed: Vec<_> = self
+                .renamed
+                .iter()
+                .map(|(expected, actual)| format!("{} -> {}", expected, actual))
+                .collect();
+            changes.push(format!("renamed {}", renamed.join(", ")));
+        }
+        if self.reordered {
+            changes.push("reordered columns".to_owned());
+        }
+        write!(f, "{}", changes.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(names: &[&str]) -> ByteRecord {
+        ByteRecord::from(names.to_vec())
+    }
+
+    fn diff(expected: &[&str], actual: &[&str]) -> HeaderDiff {
+        HeaderDiff::new(&header(expected), &header(actual))
+    }
+
+    #[test]
+    fn duplicates_are_listed_once() {
+        assert!(duplicates(&header(&["a", "b"])).is_empty());
+        assert_eq!(
+            duplicates(&header(&["id", "a", "id", "b", "id", "a"])),
+            ["id", "a"]
+        );
+    }
+
+    #[test]
+    fn identical_headers_have_no_diff() {
+        assert!(diff(&["a", "b"], &["a", "b"]).is_empty());
+    }
+
+    #[test]
+    fn replaced_column_in_same_position_is_renamed() {
+        let diff = diff(&["id", "name", "score"], &["id", "full_name", "score"]);
+        assert_eq!(diff.renamed, [("name".to_owned(), "full_name".to_owned())]);
+        assert!(diff.added.is_empty() && diff.dropped.is_empty());
+        assert!(!diff.reordered);
+    }
+
+    #[test]
+    fn extra_and_missing_columns_are_added_and_dropped() {
+        let dropped = diff(&["a", "b", "c"], &["a", "b"]);
+        assert_eq!(dropped.dropped, ["c"]);
+        assert!(dropped.renamed.is_empty());
+
+        let added = diff(&["a", "b"], &["a", "b", "c"]);
+        assert_eq!(added.added, ["c"]);
+        assert!(added.renamed.is_empty());
+    }
+
+    #[test]
+    fn shared_columns_in_another_order_are_reordered() {
+        let diff = diff(&["a", "b", "c"], &["c", "a", "b"]);
+        assert!(diff.reordered);
+        assert!(diff.added.is_empty() && diff.dropped.is_empty() && diff.renamed.is_empty());
+        assert_eq!(diff.to_string(), "reordered columns");
+    }
+
+    #[test]
+    fn rename_and_reorder_are_both_reported() {
+        let diff = diff(&["a", "b", "c"], &["x", "c", "b"]);
+        assert_eq!(diff.renamed, [("a".to_owned(), "x".to_owned())]);
+        assert!(diff.reordered);
+        assert_eq!(diff.to_string(), "renamed a -> x; reordered columns");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::compression::Compression;
+use crate::Input;
+use csv::ByteRecord;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// The extensions of input files that are read as JSON, rather than CSV.
+const EXTENSIONS: [&str; 3] = ["json", "jsonl", "ndjson"];
+
+/// Returns `true` if a path has a JSON extension, ignoring any compression
+/// extension (e.g. `events.jsonl.gz`).
+///
+/// JSON files are told apart by their extension rather than their contents,
+/// as a CSV file's first field may start with a brace or a bracket.
+pub(crate) fn is_json(path: &Path) -> bool {
+    let uncompressed;
+    let path = match Compression::from_path(path) {
+        Compression::None => path,
+        _ => {
+            uncompressed = path.with_extension("");
+            &uncompressed
+        }
+    };
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|other| extension.eq_ignore_ascii_case(other))
+        })
+}
+
+/// Reads the header of a JSON input file: the union of its objects' flattened
+/// keys (see [`flatten`]), in the order that they first appear.
+///
+/// Every object in the file is read, since any of them may add a key.
+pub(crate) fn header(reader: Input) -> serde_json::Result<ByteRecord> {
+    let mut keys = Vec::new();
+    let mut seen = HashSet::new();
+    let mut fields = Vec::new();
+    for object in objects(reader) {
+        flatten(object?, None, &mut fields);
+        for (key, _) in fields.drain(..) {
+            if !seen.contains(&key) {
+                seen.insert(key.clone());
+                keys.push(key);
+            }
+        }
+    }
+    Ok(keys.iter().collect())
+}
+
+/// Reads the rows of a JSON input file, with a field for each of the header's
+/// columns (left empty if an object doesn't have the column's key).
+pub(crate) fn rows(
+    reader: Input,
+    header: &ByteRecord,
+) -> impl Iterator<Item = serde_json::Result<ByteRecord>> {
+    let columns: HashMap<_, _> = header
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name.to_vec(), index))
+        .collect();
+    let mut fields = Vec::new();
+    let mut values = vec![Vec::new(); header.len()];
+    objects(reader).map(move |object| {
+        flatten(object?, None, &mut fields);
+        values.iter_mut().for_each(Vec::clear);
+        for (key, field) in fields.drain(..) {
+            if let Some(&index) = columns.get(key.as_bytes()) {
+                values[index] = field;
+            }
+        }
+        Ok(values.iter().collect())
+    })
+}
+
+/// Reads the objects in a JSON input file, which is either newline-delimited
+/// JSON (NDJSON) with an object on each line, or an array of objects.
+///
+/// NDJSON files are streamed, while arrays are read into memory in their
+/// entirety.
+fn objects(reader: Input) -> impl Iterator<Item = serde_json::Result<Map<String, Value>>> {
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter::<Value>()
+        .flat_map(|value| {
+            let values: Vec<_> = match value {
+                Ok(Value::Array(values)) => values.into_iter().map(Ok).collect(),
+                value => vec![value],
+            };
+            values.into_iter().map(|value| value.and_then(object))
+        })
+}
+
+/// Converts a JSON value into an object, failing if it's any other type.
+fn object(value: Value) -> serde_json::Result<Map<String, Value>> {
+    match value {
+        Value::Object(object) => Ok(object),
+        // Deserializing any other value as an object fails with an error
+        // describing the value's type
+        value => serde_json::from_value(value),
+    }
+}
+
+/// Flattens an object into its fields, joining the keys of nested objects with
+/// dots (e.g. `{"a": {"b": 1}}` has the field `a.b`).
+///
+/// Strings are written as-is and nulls as empty fields, while any other value
+/// (e.g. a number or an array) is written as JSON.
+fn flatten(object: Map<String, Value>, prefix: Option<&str>, fields: &mut Vec<(String, Vec<u8>)>) {
+    for (key, value) in object {
+        let key = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key,
+        };
+        let field = match value {
+            Value::Object(object) => {
+                flatten(object, Some(&key), fields);
+                continue;
+            }
+            Value::Null => Vec::new(),
+            Value::String(value) => value.into_bytes(),
+            value => value.to_string().into_bytes(),
+        };
+        fields.push((key, field));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_json_checks_the_extension_before_compression() {
+        for path in [
+            "events.jsonl",
+            "a/events.json",
+            "events.NDJSON",
+            "events.jsonl.gz",
+        ] {
+            assert!(is_json(Path::new(path)), "{}", path);
+        }
+        for path in ["data.csv", "data.csv.gz", "json", "events.jsonl.bak"] {
+            assert!(!is_json(Path::new(path)), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,465 @@
+//! A library for aggregating CSV files from many folders into a single output,
+//! with columns describing the folder that each row originated from.
+//!
+//! The [`Aggregator`] builder is the main entry point, and is what powers the
+//! `alligregator` binary.
+
+mod aggregator;
+mod columnar;
+pub mod compression;
+pub mod dialect;
+pub mod discovery;
+pub mod encoding;
+mod error;
+pub mod header;
+mod json;
+mod output;
+mod parallel;
+pub mod source;
+mod sqlite;
+
+pub use aggregator::{Aggregator, Summary};
+pub use error::{Error, Result};
+
+use clap::ValueEnum;
+use compression::{Compression, Encoder};
+use dialect::Dialect;
+use encoding::{OutputEncoding, Transcoder};
+use encoding_rs::Encoding;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Seek};
+use std::path::{Path, PathBuf};
+
+/// The extensions of Parquet and Arrow files, which are tried when an input
+/// file isn't found (see [`variants`]).
+const COLUMNAR_EXTENSIONS: [&str; 3] = ["parquet", "arrow", "feather"];
+
+/// An opened (and, if necessary, decompressed) input file.
+pub type Input = Box<dyn BufRead + Send>;
+
+/// An opened input file, in the format detected from its contents (or, for
+/// JSON files, from its extension).
+pub enum Opened {
+    /// A CSV file, transcoded to UTF-8.
+    Csv(Input),
+    /// An Apache Parquet file.
+    Parquet(File),
+    /// An Arrow IPC file (also known as Feather V2).
+    Arrow(File),
+    /// An Arrow IPC stream.
+    ArrowStream(Input),
+    /// A JSON file (with a `.json`, `.jsonl` or `.ndjson` extension) containing
+    /// newline-delimited objects (NDJSON) or an array of objects, transcoded to
+    /// UTF-8.
+    Json(Input),
+}
+
+/// Different kinds of input file, told apart by their first few bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum InputKind {
+    /// An Apache Parquet file.
+    Parquet,
+    /// An Arrow IPC file (also known as Feather V2).
+    ArrowFile,
+    /// An Arrow IPC stream.
+    ArrowStream,
+    /// A text file, which is either CSV or JSON (told apart by the file's
+    /// extension).
+    Text,
+}
+
+impl InputKind {
+    /// Detects the kind of a file from its first few bytes (its "magic
+    /// bytes"), which is assumed to be text unless they're Parquet or Arrow.
+    fn detect(magic: &[u8]) -> Self {
+        match magic {
+            [b'P', b'A', b'R', b'1', ..] => InputKind::Parquet,
+            [b'A', b'R', b'R', b'O', b'W', b'1', ..] => InputKind::ArrowFile,
+            // Arrow IPC streams start with a continuation marker
+            [0xff, 0xff, 0xff, 0xff, ..] => InputKind::ArrowStream,
+            _ => InputKind::Text,
+        }
+    }
+}
+
+/// Different error modes that control the program's behaviour when an input
+/// file is not found in one of the provided folders (or a folder doesn't match
+/// the pattern).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ErrorMode {
+    /// The program should fail (and exit with an error) if an input file is
+    /// not found.
+    #[value(alias = "panic")]
+    Fail,
+    /// The program should silently ignore and skip missing input files.
+    Skip,
+    /// The program should skip missing input files, but always report each
+    /// skipped folder as a warning.
+    Warn,
+    /// The program should process every input file that it can, and then fail
+    /// with a list of every folder whose input file was missing or unreadable.
+    Collect,
+}
+
+/// Different header modes that control how the headers of the input files are
+/// reconciled into the output file's header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum HeaderMode {
+    /// The first input file's header is used, and every other input file is
+    /// assumed to have the same columns in the same order.
+    First,
+    /// The union of every input file's columns is used, and rows are
+    /// re-ordered by column name (with empty cells for missing columns).
+    Union,
+    /// The first input file's header is used, and the program should fail with
+    /// a report of every input file whose header differs.
+    Strict,
+}
+
+/// Different quoting styles that control when fields in the output CSV file
+/// (including the added folder column) are wrapped in quotes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum QuoteStyle {
+    /// Every field is quoted.
+    Always,
+    /// Only fields containing a delimiter, quote or line break are quoted.
+    Necessary,
+    /// Every field that isn't a number is quoted.
+    NonNumeric,
+    /// No field is ever quoted (which may produce an invalid CSV file!).
+    Never,
+}
+
+impl From<QuoteStyle> for csv::QuoteStyle {
+    fn from(style: QuoteStyle) -> Self {
+        match style {
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        }
+    }
+}
+
+/// Different formats that the aggregated rows can be written in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Format {
+    /// Rows are written as CSV, with a header.
+    Csv,
+    /// Rows are written as newline-delimited JSON (NDJSON): one object per
+    /// row, keyed by the header's names.
+    Ndjson,
+    /// Rows are written as a JSON array of objects, keyed by the header's
+    /// names.
+    Json,
+    /// Rows are written as an Apache Parquet file, with a schema inferred from
+    /// the rows' values (integers, floats, booleans, dates or strings).
+    Parquet,
+    /// Rows are written as an Arrow IPC file (also known as Feather V2), with
+    /// a schema inferred like Parquet's.
+    Arrow,
+    /// Rows are written as an Arrow IPC stream, with a schema inferred like
+    /// Parquet's.
+    ArrowStream,
+    /// Rows are inserted into a table in a SQLite database, which can only be
+    /// written to a file (see [`Aggregator::run_sqlite`]).
+    Sqlite,
+}
+
+impl Format {
+    /// The extension conventionally used for files in this format (without a
+    /// leading `.`).
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+            Format::Json => "json",
+            Format::Parquet => "parquet",
+            Format::Arrow => "arrow",
+            Format::ArrowStream => "arrows",
+            Format::Sqlite => "db",
+        }
+    }
+
+    /// Returns `true` if the format is binary, rather than text (which can be
+    /// transcoded to another encoding).
+    pub fn is_binary(self) -> bool {
+        matches!(
+            self,
+            Format::Parquet | Format::Arrow | Format::ArrowStream | Format::Sqlite
+        )
+    }
+
+    /// Returns `true` if the format can only contain Unicode text, so fields
+    /// that aren't valid UTF-8 can't be written without replacing bytes.
+    pub fn requires_utf8(self) -> bool {
+        matches!(
+            self,
+            Format::Ndjson | Format::Json | Format::Parquet | Format::Arrow | Format::ArrowStream
+        )
+    }
+}
+
+/// Different line endings that terminate each record in the output CSV file.
+///
+/// Line breaks inside quoted fields are written as they were read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LineEnding {
+    /// Records end with a line feed (`\n`).
+    Lf,
+    /// Records end with a carriage return and line feed (`\r\n`).
+    Crlf,
+}
+
+impl From<LineEnding> for csv::Terminator {
+    fn from(ending: LineEnding) -> Self {
+        match ending {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::Crlf => csv::Terminator::CRLF,
+        }
+    }
+}
+
+/// Parses a field delimiter, which is either a single ASCII character (e.g.
+/// `;` or `|`) or one of the names `comma`, `tab`, `pipe`, `semicolon` or
+/// `space` (`\t` is also accepted for tabs).
+///
+/// # Errors
+///
+/// The function will return an error if the delimiter isn't a single ASCII
+/// character or a known name, or if it's the quote character (`"`) or a line
+/// break, which can't separate fields.
+///
+/// # Examples
+///
+/// ```
+/// use alligregator::parse_delimiter;
+///
+/// assert_eq!(parse_delimiter("tab")?, b'\t');
+/// assert_eq!(parse_delimiter(";")?, b';');
+/// assert!(parse_delimiter("\"").is_err());
+/// assert!(parse_delimiter("\n").is_err());
+/// assert!(parse_delimiter("\r").is_err());
+/// # Ok::<(), alligregator::Error>(())
+/// ```
+pub fn parse_delimiter(value: &str) -> Result<u8> {
+    match value {
+        "comma" => Ok(b','),
+        "tab" | "\\t" => Ok(b'\t'),
+        "pipe" => Ok(b'|'),
+        "semicolon" => Ok(b';'),
+        "space" => Ok(b' '),
+        _ => match value.as_bytes() {
+            [byte @ (b'"' | b'\n' | b'\r')] => Err(Error::InvalidArgument(format!(
+                "Delimiter {:?} can't be the quote character or a line break",
+                *byte as char
+            ))),
+            [byte] if byte.is_ascii() => Ok(*byte),
+            _ => Err(Error::InvalidArgument(format!(
+                "Delimiter '{}' isn't a single ASCII character",
+                value
+            ))),
+        },
+    }
+}
+
+/// An output file, which is (optionally) compressed and transcoded.
+pub type Output = Transcoder<Encoder<BufWriter<File>>>;
+
+/// Creates the output file that will contain the aggregated CSV data.
+///
+/// This function first attempts to create a file at the provided path: if the
+/// file already exists, it is truncated.
+///
+/// After this, a BufWriter is initialized for the newly created/truncated file,
+/// which is wrapped in an encoder if the output should be compressed. Finally,
+/// the output is wrapped in a transcoder, which writes the output encoding's
+/// byte order mark (BOM), if any, and transcodes everything written to it.
+///
+/// Both wrappers must be finished once everything has been written.
+///
+/// # Errors
+///
+/// The function will return an error if the program doesn't have write
+/// permissions for the provided file path, if the compression level isn't
+/// supported, or if any other generic error is encountered during the file
+/// creation.
+///
+/// # Examples
+///
+/// ```no_run
+/// use alligregator::{compression::Compression, create_output, encoding::OutputEncoding};
+/// use std::io::Write;
+///
+/// let mut out = create_output(
+///     "output.csv.gz",
+///     Compression::Gzip,
+///     None,
+///     OutputEncoding::Utf8Bom,
+/// )?;
+/// writeln!(out, "Hello world!")?;
+/// out.finish()?.finish()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn create_output(
+    path: &str,
+    compression: Compression,
+    level: Option<i32>,
+    encoding: OutputEncoding,
+) -> Result<Output> {
+    let create_error = |source: io::Error| match source.kind() {
+        ErrorKind::PermissionDenied => Error::PermissionDenied { path: path.into() },
+        ErrorKind::InvalidInput => Error::InvalidArgument(source.to_string()),
+        _ => Error::Create {
+            path: path.into(),
+            source,
+        },
+    };
+    // The level is validated first, so that an existing file isn't truncated
+    // when the level is invalid
+    compression.validate(level).map_err(create_error)?;
+    let file = File::create(path).map_err(create_error)?;
+    let encoder = Encoder::new(BufWriter::new(file), compression, level).map_err(create_error)?;
+    Transcoder::new(encoder, encoding).map_err(create_error)
+}
+
+/// Attempts to open an input file (that will be aggregated into the output
+/// file), detecting whether it's a CSV, Parquet or Arrow file from its
+/// contents. Text files with a `.json`, `.jsonl` or `.ndjson` extension
+/// (before any compression extension, e.g. `events.jsonl.gz`) are read as JSON
+/// instead of CSV.
+///
+/// This function attempts to open the file at the provided path, and then
+/// initializes a BufReader. If no file exists at the provided path, its
+/// variants (see [`variants`]) are tried instead.
+///
+/// Files compressed with gzip, zstd, bzip2 or xz are transparently
+/// decompressed, based on their magic bytes rather than their extension. The
+/// decompressed contents of CSV and JSON files are then transcoded to UTF-8
+/// from the encoding given by the file's byte order mark (BOM), or else the provided
+/// encoding (with UTF-8 assumed if no encoding is provided).
+///
+/// The function returns an option which resolves to `None` if the file was not
+/// found.
+///
+/// # Errors
+///
+/// The function will return an error if the program doesn't have read
+/// permissions for the provided file path, or if any other generic error is
+/// encountered during the read operation on the file.
+///
+/// # Examples
+///
+/// ```no_run
+/// use alligregator::{open_input, Opened};
+/// use std::path::Path;
+///
+/// let mut reader = match open_input(Path::new("folder/data.csv"), None)? {
+///     Some(Opened::Csv(reader)) => reader,
+///     Some(_) => panic!("Not a CSV file!"),
+///     None => panic!("File not found!"),
+/// };
+/// # Ok::<(), alligregator::Error>(())
+/// ```
+pub fn open_input(path: &Path, encoding: Option<&'static Encoding>) -> Result<Option<Opened>> {
+    for variant in variants(path.as_os_str()) {
+        let variant = PathBuf::from(variant);
+        let file = match File::open(&variant) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(Error::read(variant, err)),
+        };
+        return open(file, encoding, json::is_json(&variant))
+            .map(Some)
+            .map_err(|err| Error::read(variant, err));
+    }
+    Ok(None)
+}
+
+/// Detects the format of an opened input file, decompressing it if necessary.
+/// Text files are read as JSON if `json` is `true`, or else as CSV.
+fn open(file: File, encoding: Option<&'static Encoding>, json: bool) -> io::Result<Opened> {
+    let seekable = |kind, file| match kind {
+        InputKind::Parquet => Opened::Parquet(file),
+        _ => Opened::Arrow(file),
+    };
+
+    let mut reader = BufReader::new(file);
+    if let kind @ (InputKind::Parquet | InputKind::ArrowFile) =
+        InputKind::detect(reader.fill_buf()?)
+    {
+        let mut file = reader.into_inner();
+        file.rewind()?;
+        return Ok(seekable(kind, file));
+    }
+
+    let mut reader = compression::decompress(reader)?;
+    Ok(match InputKind::detect(reader.fill_buf()?) {
+        // Parquet and Arrow files can only be read with seeking, so compressed
+        // files are decompressed to a temporary file first
+        kind @ (InputKind::Parquet | InputKind::ArrowFile) => {
+            let mut file = tempfile::tempfile()?;
+            io::copy(&mut reader, &mut file)?;
+            file.rewind()?;
+            seekable(kind, file)
+        }
+        InputKind::ArrowStream => Opened::ArrowStream(reader),
+        InputKind::Text if json => Opened::Json(encoding::decode(reader, encoding)),
+        InputKind::Text => Opened::Csv(encoding::decode(reader, encoding)),
+    })
+}
+
+/// Returns the provided input file name, followed by each of its compressed
+/// variants, and then the name with each Parquet or Arrow extension instead of
+/// its own (e.g. `data.csv`, `data.csv.gz`, `data.csv.zst`, etc., followed by
+/// `data.parquet`, `data.arrow` and `data.feather`).
+///
+/// This allows folders that have migrated to Parquet or Arrow to be aggregated
+/// alongside folders that still have CSV files.
+pub fn variants(name: &OsStr) -> Vec<OsString> {
+    let path = Path::new(name);
+    let columnar = COLUMNAR_EXTENSIONS
+        .iter()
+        .filter(|&&extension| path.extension() != Some(OsStr::new(extension)))
+        .map(|extension| path.with_extension(extension).into_os_string());
+    compression::variants(name)
+        .into_iter()
+        .chain(columnar)
+        .collect()
+}
+
+/// Wraps an opened input file in a CSV reader for the provided dialect.
+///
+/// Records are parsed according to RFC 4180, so quoted fields may contain
+/// delimiters, escaped (doubled) quotes and embedded CR/LF line breaks. The
+/// reader is flexible, meaning rows aren't required to have the same number of
+/// fields as the header.
+pub(crate) fn csv_reader(reader: Input, dialect: &Dialect) -> csv::Reader<Input> {
+    csv::ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .has_headers(dialect.has_headers)
+        .flexible(true)
+        .from_reader(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn csv_starting_with_a_brace_is_read_as_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.csv");
+        std::fs::write(&path, "{x},y\n1,2\n").unwrap();
+
+        let Some(Opened::Csv(mut reader)) = open_input(&path, None).unwrap() else {
+            panic!("expected a CSV file");
+        };
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "{x},y\n1,2\n");
+    }
+}
@@ -1,21 +1,39 @@
-use clap::{ArgEnum, Parser};
-use std::fs::File;
-use std::io::{prelude::*, BufReader, BufWriter, ErrorKind};
+use alligregator::compression::Compression;
+use alligregator::encoding::{self, OutputEncoding};
+use alligregator::{
+    create_output, parse_delimiter, Aggregator, Error, ErrorMode, Format, HeaderMode, LineEnding,
+    QuoteStyle, Result,
+};
+use clap::{ArgGroup, Parser};
+use encoding_rs::Encoding;
+use regex::Regex;
+use std::io::Write;
 use std::path::Path;
+use std::process;
 
-/// Different error modes that control the program's behaviour when an input
-/// file is not found in one of the provided folders.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
-enum ErrorMode {
-    /// The program should panic (and abort) if an input file is not found.
-    Panic,
-    /// The program should silently ignore and skip missing input files.
-    Skip,
-}
+/// The exit codes of the program, which are listed in the CLI's help.
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  The aggregation succeeded
+  2  Invalid arguments (including an existing SQLite table, without `--append`)
+  3  Missing input (file not found, or folder doesn't match the pattern)
+  4  Permission denied
+  5  Malformed input (invalid CSV, JSON, Parquet or Arrow data, invalid UTF-8
+     for JSON, Parquet, Arrow or UTF-16LE output, mismatched headers, or
+     SQLite table columns that don't match)
+  6  Failed to read an input file or directory
+  7  Failed to create or write the output file
+  8  One or more folders failed (with `--error collect`)";
 
 /// The program's CLI arguments.
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, after_help = EXIT_CODES)]
+#[clap(group(
+    ArgGroup::new("sources")
+        .required(true)
+        .multiple(true)
+        .args(["folders", "glob", "recursive"])
+))]
 struct Args {
     /// The name of the input file to look for in each folder.
     #[clap(short, long)]
@@ -29,18 +47,85 @@ struct Args {
 
     /// A comma-delimited list of folders to look in for the input file.
     #[clap(short = 'F', long)]
-    folders: String,
+    folders: Option<String>,
+
+    /// A glob pattern (relative to the root directory) matching folders to look
+    /// in for the input file, e.g. `2024-*` or `region/*/daily`.
+    ///
+    /// This option can be provided multiple times.
+    #[clap(short, long)]
+    glob: Vec<String>,
 
-    /// The name of the column that is added to the output CSV file, containing
+    /// Should every folder under the root directory that contains the input
+    /// file be found recursively?
+    #[clap(short = 'R', long)]
+    recursive: bool,
+
+    /// The name of the column that is added to the output file, containing
     /// the name of the folder that each row originated from.
+    #[clap(short, long, required_unless_present_any = ["partitions", "pattern"])]
+    column: Option<String>,
+
+    /// Should Hive-style `key=value` segments of the folders' paths (e.g.
+    /// `year=2024/month=03`) be added to the output file, with a column
+    /// for each key?
     #[clap(short, long)]
-    column: String,
+    partitions: bool,
+
+    /// A regular expression that is applied to each folder's path, adding a
+    /// column to the output file for each of its named capture groups (e.g.
+    /// `run_(?P<run>\d+)_(?P<date>[\d-]+)`).
+    ///
+    /// Folders that don't match the pattern are treated like missing input
+    /// files, according to the error mode.
+    #[clap(short = 'x', long, value_parser = Regex::new)]
+    pattern: Option<Regex>,
+
+    /// The encoding of the input files (e.g. `utf-16le`, `windows-1252` or
+    /// `latin1`), which are transcoded to UTF-8.
+    ///
+    /// A byte order mark (BOM) at the start of an input file takes precedence
+    /// over this encoding. By default, input files without a BOM are assumed
+    /// to be UTF-8.
+    #[clap(long, value_parser = encoding::for_label)]
+    input_encoding: Option<&'static Encoding>,
+
+    /// The delimiter that separates fields in the input files: a single
+    /// character (other than `"` or a line break), or one of `comma`, `tab`,
+    /// `pipe`, `semicolon` or `space`.
+    ///
+    /// By default, fields are separated by commas.
+    #[clap(short, long, value_parser = parse_delimiter, default_value = "comma")]
+    delimiter: u8,
+
+    /// Should the dialect of each input file (its delimiter, quote character
+    /// and whether it has a header) be sniffed from its first few kilobytes?
+    ///
+    /// `--delimiter` is used for input files whose delimiter can't be
+    /// sniffed. Rows from input files without a header are assumed to have
+    /// the output header's columns, in order.
+    #[clap(long)]
+    sniff: bool,
 
-    /// The name of the output CSV file.
+    /// The format that the output file is written in.
     ///
-    /// By default, the output file is `output.csv`.
-    #[clap(short, long, default_value = "output.csv")]
-    output: String,
+    /// By default, the output is written as CSV.
+    #[clap(long, value_enum, default_value = "csv")]
+    format: Format,
+
+    /// The delimiter that separates fields in the output CSV file (including
+    /// the folder columns), in the same format as `--delimiter`.
+    ///
+    /// By default, fields are separated by commas.
+    #[clap(long, value_parser = parse_delimiter, default_value = "comma")]
+    output_delimiter: u8,
+
+    /// The name of the output file.
+    ///
+    /// By default, the output file is named `output` with the format's
+    /// extension (e.g. `output.csv`, `output.parquet` or `output.db`).
+    #[clap(short, long)]
+    output: Option<String>,
 
     /// Should additional/debugging messages be logged?
     #[clap(short, long)]
@@ -48,142 +133,173 @@ struct Args {
 
     /// Controls the behaviour of the program when an file is not found.
     ///
-    /// By default, the behaviour is to panic (and abort).
-    #[clap(short, long, arg_enum, default_value = "panic")]
+    /// By default, the behaviour is to fail (and exit with an error).
+    #[clap(short, long, value_enum, default_value = "fail")]
     error: ErrorMode,
-}
 
-/// Creates the output file that will contain the aggregated CSV data.
-///
-/// This function first attempts to create a file at the provided path: if the
-/// file already exists, it is truncated.
-///
-/// After this, a BufWriter is initialized for the newly created/truncated file.
-///
-/// # Panics
-///
-/// The function will panic if the program doesn't have write permissions for
-/// the provided file path, or if any other generic error is encountered during
-/// the file creation.
-///
-/// # Examples
-///
-/// ```
-/// let mut out = create_output(path);
-/// writeln!(out, "Hello world!");
-/// ```
-fn create_output(path: String) -> BufWriter<File> {
-    let file = match File::create(path) {
-        Ok(file) => file,
-        Err(err) => match err.kind() {
-            ErrorKind::PermissionDenied => {
-                panic!("Permission denied when trying to create output file!")
-            }
-            other => panic!(
-                "Encountered an error when creating output file: {:?}",
-                other
-            ),
-        },
-    };
-    BufWriter::new(file)
-}
+    /// Controls when fields in the output CSV file are quoted.
+    ///
+    /// By default, fields are only quoted when necessary.
+    #[clap(long, value_enum, default_value = "necessary")]
+    quote_style: QuoteStyle,
+
+    /// The line ending that terminates each record in the output CSV file.
+    ///
+    /// By default, records end with a line feed.
+    #[clap(long, value_enum, default_value = "lf")]
+    line_ending: LineEnding,
+
+    /// The text encoding of the output file. Use `utf8-bom` for output that
+    /// will be opened in Excel.
+    ///
+    /// With `utf16le`, input files must be valid UTF-8 (after being decoded
+    /// from their input encoding), or the aggregation fails: UTF-8 output
+    /// passes invalid bytes through untouched, but UTF-16 can't contain them.
+    ///
+    /// By default, the output is UTF-8 without a byte order mark (BOM).
+    #[clap(long, value_enum, default_value = "utf8")]
+    output_encoding: OutputEncoding,
+
+    /// Controls how the headers of the input files are reconciled.
+    ///
+    /// By default, the first input file's header is used.
+    #[clap(long, value_enum, default_value = "first")]
+    headers: HeaderMode,
+
+    /// The compression format of the output file.
+    ///
+    /// By default, the format is detected from the output file's extension
+    /// (e.g. `output.csv.gz` or `output.csv.zst`).
+    #[clap(long, value_enum)]
+    compression: Option<Compression>,
+
+    /// The compression level of the output file (e.g. 0-9 for gzip, or -7-22
+    /// for zstd).
+    ///
+    /// By default, the compression format's default level is used.
+    #[clap(long, allow_negative_numbers = true)]
+    compression_level: Option<i32>,
 
-/// Attempts to open an input CSV file (that will be aggregated into the output
-/// file).
-///
-/// This function attempts to open the file at the provided path, and then
-/// initializes a BufReader.
-///
-/// The function returns an option which resolves to `None` if the file was not
-/// found.
-///
-/// # Panics
-///
-/// The function will panic if the program doesn't have read permissions for
-/// the provided file path, or if any other generic error is encountered during
-/// the read operation on the file.
-///
-/// # Examples
-///
-/// ```
-/// let mut reader = match open_input(&path) {
-///     Some(file) => file,
-///     None => panic!("File not found!"),
-/// };
-/// ```
-fn open_input(path: &Path) -> Option<BufReader<File>> {
-    let folder = path.parent().unwrap().as_os_str().to_string_lossy();
-    let file = match File::open(path) {
-        Ok(file) => Some(file),
-        Err(err) => match err.kind() {
-            ErrorKind::NotFound => None,
-            ErrorKind::PermissionDenied => panic!(
-                "Permission denied when trying to read file in folder '{}'!",
-                folder
-            ),
-            other => panic!(
-                "Encountered an error when reading file in folder '{}': {:?}",
-                folder, other
-            ),
-        },
-    };
-    file.map(BufReader::new)
+    /// The name of the table that rows are inserted into, for SQLite output.
+    ///
+    /// By default, a new table is created for each run, named after the
+    /// current time (e.g. `run_1700000000`).
+    #[clap(long)]
+    table: Option<String>,
+
+    /// Should rows be appended to the table if it already exists, for SQLite
+    /// output? The output's columns are matched to the table's columns by
+    /// name.
+    #[clap(long)]
+    append: bool,
+
+    /// The number of threads used to read the input files concurrently.
+    ///
+    /// By default, the number of available CPUs is used.
+    #[clap(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Should rows be written as soon as they're read, rather than in the
+    /// order of the input folders? This maximises throughput when reading
+    /// input files concurrently.
+    #[clap(short, long)]
+    unordered: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    let mut out = create_output(args.output);
-    let mut lines = Vec::new();
-    let mut found_header = false;
+    if let Err(err) = run(args) {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+/// Runs the aggregation described by the CLI arguments.
+fn run(args: Args) -> Result<()> {
+    if args.format.is_binary() && args.output_encoding != OutputEncoding::Utf8 {
+        return Err(Error::InvalidArgument(format!(
+            "The output encoding can't be set for {:?} output",
+            args.format
+        )));
+    }
+
+    if args.format == Format::Sqlite
+        && (args.compression.is_some() || args.compression_level.is_some())
+    {
+        return Err(Error::InvalidArgument(
+            "The compression format and level can't be set for Sqlite output".to_owned(),
+        ));
+    }
+
+    let output = args
+        .output
+        .unwrap_or_else(|| format!("output.{}", args.format.extension()));
+
+    // Skipped folders are reported as soon as they're skipped, so that they're
+    // reported even if the aggregation later fails
+    let (error, verbose) = (args.error, args.verbose);
 
     // Expect folder names to be comma-delimited
-    for folder in args.folders.split(',') {
-        let path = Path::new(&args.root).join(folder).join(&args.filename);
-        let mut reader = match open_input(&path) {
-            Some(reader) => reader,
-            None => {
-                // Skip files that don't exist if `--error=skip`
-                if args.error == ErrorMode::Skip {
-                    if args.verbose {
-                        println!("Couldn't find file in folder '{}', so skipping...", folder);
-                    }
-                    continue;
-                } else {
-                    panic!("Couldn't find file in folder '{}'!", folder)
-                }
+    let folders = args.folders.iter().flat_map(|folders| folders.split(','));
+    let mut aggregator = Aggregator::new(args.filename)
+        .root(args.root)
+        .folders(folders)
+        .recursive(args.recursive)
+        .partitions(args.partitions)
+        .error_mode(args.error)
+        .on_skip(move |skipped| {
+            if error == ErrorMode::Warn {
+                eprintln!("warning: {}, so skipping...", skipped);
+            } else if verbose {
+                println!("{}, so skipping...", skipped);
             }
-        };
-
-        // Read the header, but only include if the header hasn't been found yet (to avoid dupes)
-        let mut header = String::new();
-        reader
-            .read_line(&mut header)
-            .unwrap_or_else(|_| panic!("Failed to read header from file in folder '{}'!", folder));
-        if !found_header {
-            lines.extend_from_slice(
-                vec![
-                    args.column.clone(),
-                    ",".to_owned(),
-                    header.replace("\r\n", "\n"),
-                ]
-                .as_slice(),
-            );
-            found_header = true;
-        }
-
-        for line in reader.lines().filter_map(|result| result.ok()) {
-            lines.extend_from_slice(
-                vec![
-                    folder.to_owned(),
-                    String::from(","),
-                    line,
-                    String::from("\n"),
-                ]
-                .as_slice(),
-            );
-        }
+        })
+        .header_mode(args.headers)
+        .delimiter(args.delimiter)
+        .sniff(args.sniff)
+        .format(args.format)
+        .output_delimiter(args.output_delimiter)
+        .output_encoding(args.output_encoding)
+        .quote_style(args.quote_style)
+        .line_ending(args.line_ending)
+        .append(args.append)
+        .ordered(!args.unordered);
+    for pattern in args.glob {
+        aggregator = aggregator.glob(pattern);
+    }
+    if let Some(column) = args.column {
+        aggregator = aggregator.column(column);
+    }
+    if let Some(pattern) = args.pattern {
+        aggregator = aggregator.pattern(pattern);
+    }
+    if let Some(encoding) = args.input_encoding {
+        aggregator = aggregator.input_encoding(encoding);
+    }
+    if let Some(table) = args.table {
+        aggregator = aggregator.table(table);
+    }
+    if let Some(threads) = args.threads {
+        aggregator = aggregator.threads(threads);
     }
 
-    out.write_all(lines.join("").as_bytes())
-        .expect("Failed to write to output file!");
+    if args.format == Format::Sqlite {
+        aggregator.run_sqlite(&output)?;
+    } else {
+        let compression = args
+            .compression
+            .unwrap_or_else(|| Compression::from_path(Path::new(&output)));
+        let mut out = create_output(
+            &output,
+            compression,
+            args.compression_level,
+            args.output_encoding,
+        )?;
+        aggregator.run(&mut out)?;
+        out.finish()
+            .and_then(|out| out.finish())
+            .and_then(|mut out| out.flush())
+            .map_err(Error::Write)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,158 @@
+use crate::columnar::ColumnarWriter;
+use crate::header;
+use crate::sqlite::SqliteWriter;
+use crate::{Error, Format, LineEnding, Result};
+use csv::ByteRecord;
+use std::io::Write;
+
+/// Writes the aggregated records (the header, followed by each row) in an
+/// output format.
+pub(crate) enum RecordWriter<W: Write + Send> {
+    /// Records are written as CSV.
+    Csv(Box<csv::Writer<W>>),
+    /// Records are written as JSON objects keyed by the header's names.
+    Json(JsonWriter<W>),
+    /// Records are written in a columnar format, with an inferred schema.
+    Columnar(Box<ColumnarWriter<W>>),
+    /// Records are inserted into a SQLite table.
+    Sqlite(Box<SqliteWriter>),
+}
+
+impl<W: Write + Send> RecordWriter<W> {
+    /// Writes the header, which must be written before any rows.
+    pub fn write_header(&mut self, header: &ByteRecord) -> Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => Ok(writer.write_byte_record(header)?),
+            RecordWriter::Json(writer) => {
+                check_unique(header, "JSON")?;
+                writer.keys = header.iter().map(json_string).collect();
+                Ok(())
+            }
+            RecordWriter::Columnar(writer) => {
+                writer.write_header(header);
+                Ok(())
+            }
+            RecordWriter::Sqlite(writer) => writer.write_header(header),
+        }
+    }
+
+    /// Writes a row.
+    pub fn write_row(&mut self, row: &ByteRecord) -> Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => Ok(writer.write_byte_record(row)?),
+            RecordWriter::Json(writer) => writer.write_row(row),
+            RecordWriter::Columnar(writer) => writer.write_row(row),
+            RecordWriter::Sqlite(writer) => writer.write_row(row),
+        }
+    }
+
+    /// Writes the end of the output (if the format has one), and flushes it.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            RecordWriter::Csv(mut writer) => writer.flush().map_err(Error::Write),
+            RecordWriter::Json(writer) => writer.finish(),
+            RecordWriter::Columnar(writer) => writer.finish(),
+            RecordWriter::Sqlite(writer) => writer.finish(),
+        }
+    }
+}
+
+/// Writes rows as JSON objects, either one per line (NDJSON) or as the
+/// elements of a single array.
+pub(crate) struct JsonWriter<W: Write> {
+    writer: W,
+    /// Whether rows are written as the elements of an array, rather than one
+    /// per line.
+    array: bool,
+    terminator: &'static [u8],
+    /// The header's names, already encoded as JSON strings.
+    keys: Vec<String>,
+    rows: u64,
+}
+
+impl<W: Write> JsonWriter<W> {
+    /// Creates a writer for a JSON format (NDJSON or a JSON array), with each
+    /// object followed by the provided line ending.
+    pub fn new(writer: W, format: Format, line_ending: LineEnding) -> Self {
+        JsonWriter {
+            writer,
+            array: format == Format::Json,
+            terminator: match line_ending {
+                LineEnding::Lf => b"\n",
+                LineEnding::Crlf => b"\r\n",
+            },
+            keys: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    /// Writes a row as an object. Fields beyond the end of the header are
+    /// keyed by their (1-based) position, e.g. `column5`, and missing fields
+    /// are left out.
+    fn write_row(&mut self, row: &ByteRecord) -> Result<()> {
+        let mut object = Vec::new();
+        if self.array {
+            object.extend_from_slice(if self.rows == 0 { b"[" } else { b"," });
+            object.extend_from_slice(self.terminator);
+        }
+        object.push(b'{');
+        for (index, field) in row.iter().enumerate() {
+            if index > 0 {
+                object.push(b',');
+            }
+            let key = match self.keys.get(index) {
+                Some(key) => key.clone(),
+                None => json_string(format!("column{}", index + 1)),
+            };
+            object.extend_from_slice(key.as_bytes());
+            object.push(b':');
+            object.extend_from_slice(json_string(field).as_bytes());
+        }
+        object.push(b'}');
+        if !self.array {
+            object.extend_from_slice(self.terminator);
+        }
+
+        self.rows += 1;
+        self.writer.write_all(&object).map_err(Error::Write)
+    }
+
+    /// Closes the array (if rows are written as an array), and flushes the
+    /// output.
+    fn finish(mut self) -> Result<()> {
+        if self.array {
+            let start: &[u8] = if self.rows == 0 { b"[" } else { b"" };
+            let end = [start, self.terminator, b"]", self.terminator].concat();
+            self.writer.write_all(&end).map_err(Error::Write)?;
+        }
+        self.writer.flush().map_err(Error::Write)
+    }
+}
+
+/// Checks that the names in a header are unique, for formats that key fields by
+/// name (where fields with the same name would overwrite each other).
+///
+/// Names can be repeated if a source column has the same name as an input
+/// file's column, or if an input file's header repeats a name.
+pub(crate) fn check_unique(header: &ByteRecord, format: &str) -> Result<()> {
+    let duplicates = header::duplicates(header);
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<_> = duplicates
+        .iter()
+        .map(|name| format!("'{}'", name))
+        .collect();
+    Err(Error::InvalidArgument(format!(
+        "Columns {} appear more than once in the header, but {} output needs unique column names",
+        names.join(", "),
+        format
+    )))
+}
+
+/// Encodes a field as a JSON string. Fields are checked to be valid UTF-8 as
+/// they're read, so no bytes are replaced.
+fn json_string(field: impl AsRef<[u8]>) -> String {
+    let field = String::from_utf8_lossy(field.as_ref());
+    serde_json::to_string(&field).expect("strings are always serializable")
+}
@@ -0,0 +1,279 @@
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// The number of items sent from a worker thread to the consumer at once.
+const BATCH_SIZE: usize = 1024;
+
+/// The number of batches that can be waiting to be consumed (per job in ordered
+/// mode, or per worker thread in unordered mode) before a worker thread blocks.
+const CHANNEL_BATCHES: usize = 4;
+
+/// A batch of items emitted by a job, or the error that the job failed with.
+type Batch<T, E> = Result<Vec<T>, E>;
+
+/// Runs the `work` function for every job concurrently across a pool of worker
+/// threads, passing each item that it emits to the `consume` function on the
+/// calling thread.
+///
+/// If `ordered` is `true`, items are consumed in the original order of the jobs
+/// (and the order each job emitted them in). Otherwise, items are consumed as
+/// soon as they're available, for maximum throughput.
+///
+/// Jobs are claimed by worker threads in their original order, and the number
+/// of items waiting to be consumed is bounded by the number of threads, so
+/// memory usage stays constant regardless of how many jobs there are or how
+/// many items each job emits. In ordered mode, a job can't be claimed until
+/// every job more than `threads` places before it has been consumed.
+///
+/// The first error returned by a job (in the order that items are consumed) or
+/// by the `consume` function stops every worker thread, and is returned. The
+/// `emit` function passed to each job returns `false` once the workers have
+/// been stopped, so that jobs can finish early.
+pub fn process<J, T, E, W, C>(
+    jobs: &[J],
+    threads: usize,
+    ordered: bool,
+    work: W,
+    mut consume: C,
+) -> Result<(), E>
+where
+    J: Sync,
+    T: Send,
+    E: Send,
+    W: Fn(&J, &mut dyn FnMut(T) -> bool) -> Result<(), E> + Sync,
+    C: FnMut(T) -> Result<(), E>,
+{
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let threads = threads.clamp(1, jobs.len().max(1));
+
+    // Runs jobs (claimed in order) until there are none left, sending each
+    // job's items to the sender returned by `sender` for the job's index
+    let worker = |sender: &(dyn Fn(usize) -> Option<SyncSender<Batch<T, E>>> + Sync)| {
+        while !stopped.load(Ordering::SeqCst) {
+            let index = next.fetch_add(1, Ordering::SeqCst);
+            let (Some(job), Some(sender)) = (jobs.get(index), sender(index)) else {
+                break;
+            };
+
+            // Send errors mean that the consumer has stopped (e.g. because of
+            // an error), so every worker thread is stopped too
+            let send = |batch| {
+                let sent = sender.send(batch).is_ok();
+                if !sent {
+                    stopped.store(true, Ordering::SeqCst);
+                }
+                sent
+            };
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            let result = work(job, &mut |item| {
+                batch.push(item);
+                batch.len() < BATCH_SIZE
+                    || send(Ok(mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE))))
+            });
+            match result {
+                Ok(()) if !batch.is_empty() => send(Ok(batch)),
+                Ok(()) => true,
+                Err(err) => send(Err(err)),
+            };
+        }
+    };
+
+    // Consumes the items from a receiver, until its channel is closed
+    let mut drain = |receiver: Receiver<Batch<T, E>>| {
+        for batch in receiver {
+            batch?.into_iter().try_for_each(&mut consume)?;
+        }
+        Ok(())
+    };
+
+    if ordered {
+        // Each job has its own channel, which is drained in order: this can't
+        // deadlock, as the earliest unfinished job has always been claimed by
+        // a worker thread. Workers wait before starting a job that's too far
+        // ahead of the consumer, so that later jobs aren't buffered while an
+        // earlier job is still being consumed
+        let (senders, receivers): (Vec<_>, Vec<_>) = jobs
+            .iter()
+            .map(|_| {
+                let (sender, receiver) = mpsc::sync_channel(CHANNEL_BATCHES);
+                (Mutex::new(Some(sender)), receiver)
+            })
+            .unzip();
+        let consumed = Mutex::new(0);
+        let drained = Condvar::new();
+        let sender = |index: usize| {
+            let mut consumed = consumed.lock().unwrap();
+            while index >= *consumed + threads && !stopped.load(Ordering::SeqCst) {
+                consumed = drained.wait(consumed).unwrap();
+            }
+            if stopped.load(Ordering::SeqCst) {
+                return None;
+            }
+            senders.get(index)?.lock().unwrap().take()
+        };
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| worker(&sender));
+            }
+            let result = receivers.into_iter().try_for_each(|receiver| {
+                drain(receiver)?;
+                *consumed.lock().unwrap() += 1;
+                drained.notify_all();
+                Ok(())
+            });
+
+            // Wake any worker threads that are still waiting, so they can stop
+            stopped.store(true, Ordering::SeqCst);
+            let _consumed = consumed.lock().unwrap();
+            drained.notify_all();
+            result
+        })
+    } else {
+        let (sender, receiver) = mpsc::sync_channel(threads * CHANNEL_BATCHES);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let sender = sender.clone();
+                scope.spawn(move || worker(&|_| Some(sender.clone())));
+            }
+            drop(sender);
+            drain(receiver)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Runs jobs that each emit `items` items tagged with the job's index.
+    fn run(jobs: usize, items: usize, threads: usize, ordered: bool) -> Vec<(usize, usize)> {
+        let jobs: Vec<usize> = (0..jobs).collect();
+        let mut consumed = Vec::new();
+        let result: Result<(), ()> = process(
+            &jobs,
+            threads,
+            ordered,
+            |&job, emit| {
+                for item in 0..items {
+                    emit((job, item));
+                }
+                Ok(())
+            },
+            |item| {
+                consumed.push(item);
+                Ok(())
+            },
+        );
+        assert_eq!(result, Ok(()));
+        consumed
+    }
+
+    #[test]
+    fn ordered_items_are_consumed_in_job_order() {
+        let expected: Vec<_> = (0..20)
+            .flat_map(|job| (0..3000).map(move |item| (job, item)))
+            .collect();
+        assert_eq!(run(20, 3000, 4, true), expected);
+    }
+
+    #[test]
+    fn unordered_items_are_all_consumed() {
+        let mut consumed = run(20, 3000, 4, false);
+        consumed.sort();
+        let expected: Vec<_> = (0..20)
+            .flat_map(|job| (0..3000).map(move |item| (job, item)))
+            .collect();
+        assert_eq!(consumed, expected);
+    }
+
+    #[test]
+    fn no_jobs_consume_nothing() {
+        assert!(run(0, 10, 4, true).is_empty());
+    }
+
+    #[test]
+    fn first_job_error_in_order_is_returned() {
+        let jobs: Vec<usize> = (0..50).collect();
+        let mut consumed = Vec::new();
+        let result = process(
+            &jobs,
+            4,
+            true,
+            |&job, emit| {
+                emit(job);
+                match job {
+                    10 | 30 => Err(job),
+                    _ => Ok(()),
+                }
+            },
+            |job| {
+                consumed.push(job);
+                Ok(())
+            },
+        );
+        // The failed job's unsent items are discarded along with it
+        assert_eq!(result, Err(10));
+        assert_eq!(consumed, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn consume_error_stops_the_workers() {
+        let jobs: Vec<usize> = (0..50).collect();
+        let claimed = AtomicUsize::new(0);
+        let result = process(
+            &jobs,
+            4,
+            true,
+            |_, emit| {
+                claimed.fetch_add(1, Ordering::SeqCst);
+                while emit(()) {}
+                Ok(())
+            },
+            |()| Err("full"),
+        );
+        assert_eq!(result, Err("full"));
+        assert!(claimed.into_inner() < jobs.len());
+    }
+
+    #[test]
+    fn ordered_buffers_are_bounded_by_threads() {
+        let threads = 4;
+        let jobs: Vec<usize> = (0..64).collect();
+        let buffered = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        // The first job is slow, while every other job emits as many items as
+        // its channel can hold without blocking
+        let result: Result<(), ()> = process(
+            &jobs,
+            threads,
+            true,
+            |&job, emit| {
+                if job == 0 {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                for item in 0..CHANNEL_BATCHES * BATCH_SIZE {
+                    let count = buffered.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(count, Ordering::SeqCst);
+                    if !emit((job, item)) {
+                        break;
+                    }
+                }
+                Ok(())
+            },
+            |_| {
+                buffered.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            },
+        );
+
+        assert_eq!(result, Ok(()));
+        let bound = threads * (CHANNEL_BATCHES + 2) * BATCH_SIZE;
+        assert!(peak.into_inner() <= bound);
+    }
+}
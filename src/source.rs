@@ -0,0 +1,179 @@
+use regex::Regex;
+use std::path::Path;
+
+/// The value of a Hive-style partition that represents a missing (null) value.
+const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// The columns that are added to the output file, describing the folder that
+/// each row originated from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceColumns {
+    /// The name of the column containing the folder's path (if any).
+    pub column: Option<String>,
+    /// The keys of the Hive-style (`key=value`) partitions found in the
+    /// folders' paths, each of which is added as its own column.
+    pub partitions: Vec<String>,
+    /// A regular expression applied to the folders' paths, with each of its
+    /// named capture groups added as its own column.
+    pub pattern: Option<Regex>,
+}
+
+impl SourceColumns {
+    /// Returns the names of the added columns, in the order that they appear
+    /// in the output file.
+    pub fn header(&self) -> Vec<&str> {
+        self.column
+            .iter()
+            .chain(self.partitions.iter())
+            .map(String::as_str)
+            .chain(
+                self.pattern
+                    .iter()
+                    .flat_map(|pattern| pattern.capture_names().flatten()),
+            )
+            .collect()
+    }
+
+    /// Returns `true` if the provided folder's path matches the pattern (or if
+    /// there is no pattern).
+    pub fn matches(&self, folder: &str) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(folder))
+    }
+
+    /// Returns the values of the added columns for rows originating from the
+    /// provided folder.
+    ///
+    /// Partition keys that are absent from the folder's path, and capture
+    /// groups that don't participate in the pattern's match, have an empty
+    /// value.
+    pub fn values(&self, folder: &str) -> Vec<String> {
+        let mut values: Vec<_> = self.column.iter().map(|_| folder.to_owned()).collect();
+        if !self.partitions.is_empty() {
+            let partitions = partitions(folder);
+            values.extend(self.partitions.iter().map(|key| {
+                partitions
+                    .iter()
+                    .find(|(other, _)| other == key)
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default()
+            }));
+        }
+        if let Some(pattern) = &self.pattern {
+            let captures = pattern.captures(folder);
+            values.extend(pattern.capture_names().flatten().map(|name| {
+                captures
+                    .as_ref()
+                    .and_then(|captures| captures.name(name))
+                    .map(|group| group.as_str().to_owned())
+                    .unwrap_or_default()
+            }));
+        }
+        values
+    }
+}
+
+/// Parses the Hive-style `key=value` segments of a folder's path (e.g.
+/// `year=2024/month=03/site=lon`) into key-value pairs.
+///
+/// Segments without an `=` are ignored. Keys and values are percent-decoded,
+/// and Hive's default partition (`__HIVE_DEFAULT_PARTITION__`) becomes an
+/// empty value.
+pub fn partitions(folder: &str) -> Vec<(String, String)> {
+    Path::new(folder)
+        .components()
+        .filter_map(|component| {
+            let segment = component.as_os_str().to_string_lossy();
+            let (key, value) = segment.split_once('=')?;
+            let value = match value {
+                HIVE_DEFAULT_PARTITION => String::new(),
+                value => unescape(value),
+            };
+            Some((unescape(key), value))
+        })
+        .collect()
+}
+
+/// Collects the partition keys found in the provided folders' paths, in the
+/// order that they're first seen, so every row has a consistent set of
+/// partition columns.
+pub fn partition_keys<'a, I>(folders: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut keys = Vec::new();
+    for (key, _) in folders.into_iter().flat_map(partitions) {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// Decodes the `%XX` escape sequences that Hive uses for special characters
+/// in partition keys and values. Invalid sequences are left as they are.
+fn unescape(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|hex| bytes[index] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_decodes_percent_sequences() {
+        assert_eq!(unescape("a%3Db%2Fc"), "a=b/c");
+        assert_eq!(unescape("caf%C3%A9"), "café");
+        assert_eq!(unescape("plain"), "plain");
+    }
+
+    #[test]
+    fn unescape_leaves_invalid_sequences() {
+        assert_eq!(unescape("100%"), "100%");
+        assert_eq!(unescape("%zz%4"), "%zz%4");
+        assert_eq!(unescape("%%41"), "%A");
+    }
+
+    #[test]
+    fn partitions_parse_key_value_segments() {
+        let partitions = partitions("exports/year=2024/month=03/raw");
+        let expected = [("year", "2024"), ("month", "03")]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()));
+        assert_eq!(partitions, expected);
+    }
+
+    #[test]
+    fn partitions_decode_values_and_default_partition() {
+        let partitions = partitions("site=a%2Fb/region=__HIVE_DEFAULT_PARTITION__/v=x=y");
+        let expected = [("site", "a/b"), ("region", ""), ("v", "x=y")]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()));
+        assert_eq!(partitions, expected);
+    }
+
+    #[test]
+    fn partition_keys_are_ordered_by_first_appearance() {
+        let keys = partition_keys(["year=2024/site=a", "site=b/region=eu", "year=2023"]);
+        assert_eq!(keys, ["year", "site", "region"]);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{Error, Result};
+use csv::ByteRecord;
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{Connection, OptionalExtension};
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of rows inserted in each transaction.
+const TRANSACTION_SIZE: u64 = 10_000;
+
+/// Writes the aggregated records into a table in a SQLite database.
+pub(crate) struct SqliteWriter {
+    connection: Connection,
+    table: String,
+    /// Whether the table's name was generated, in which case a suffix is
+    /// added to it if a table with the same name already exists.
+    generated: bool,
+    /// Whether rows are appended to the table if it already exists, rather
+    /// than failing.
+    append: bool,
+    /// The number of source columns at the start of each row, which are
+    /// indexed.
+    source_columns: usize,
+    /// The statement that inserts a row, once the header has been written.
+    insert: Option<String>,
+    columns: usize,
+    rows: u64,
+}
+
+impl SqliteWriter {
+    /// Opens (or creates) the SQLite database at the provided path, to write
+    /// rows into the provided table (or else a new table named after the
+    /// current time, e.g. `run_1700000000`).
+    pub fn open(
+        path: &Path,
+        table: Option<String>,
+        append: bool,
+        source_columns: usize,
+    ) -> Result<Self> {
+        let connection = Connection::open(path).map_err(|err| Error::Create {
+            path: path.into(),
+            source: io::Error::other(err),
+        })?;
+        let generated = table.is_none();
+        let table = table.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            format!("run_{}", now.map_or(0, |now| now.as_secs()))
+        });
+        Ok(SqliteWriter {
+            connection,
+            table,
+            generated,
+            append,
+            source_columns,
+            insert: None,
+            columns: 0,
+            rows: 0,
+        })
+    }
+
+    /// Creates the table with a (text) column for each of the header's names,
+    /// and an index for each source column. An error is returned if the
+    /// header repeats a name (ignoring case), before the table is created.
+    ///
+    /// When appending to an existing table, the header's names are matched to
+    /// the table's columns instead: an error is returned if any of them aren't
+    /// in the table, while columns that aren't in the header are left null.
+    /// Otherwise, an error is returned if the table already exists, unless its
+    /// name was generated (in which case a suffix such as `_2` is added).
+    pub fn write_header(&mut self, header: &ByteRecord) -> Result<()> {
+        let names: Vec<_> = header
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+
+        // SQLite's column names are case-insensitive, so names that only
+        // differ by case can't be columns of the same table
+        let mut duplicates: Vec<_> = Vec::new();
+        for (index, name) in names.iter().enumerate() {
+            let repeated = names[..index]
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(name));
+            if repeated && !duplicates.contains(&name) {
+                duplicates.push(name);
+            }
+        }
+        if !duplicates.is_empty() {
+            let duplicates: Vec<_> = duplicates
+                .iter()
+                .map(|name| format!("'{}'", name))
+                .collect();
+            return Err(Error::InvalidArgument(format!(
+                "Columns {} appear more than once in the header (ignoring case), but SQLite \
+                 output needs unique column names",
+                duplicates.join(", ")
+            )));
+        }
+
+        // The table is checked and created in a single transaction, so that
+        // another connection can't create it in between
+        self.connection
+            .execute_batch("BEGIN IMMEDIATE")
+            .map_err(write_error)?;
+        if self.generated {
+            let name = self.table.clone();
+            let mut suffix = 1;
+            while self.table_columns()?.is_some() {
+                suffix += 1;
+                self.table = format!("{}_{}", name, suffix);
+            }
+        }
+        let table = quote(&self.table);
+
+        match self.table_columns()? {
+            Some(columns) if self.append => {
+                let unmatched: Vec<_> = names
+                    .iter()
+                    .filter(|name| !columns.contains(name))
+                    .cloned()
+                    .collect();
+                if !unmatched.is_empty() {
+                    return Err(Error::TableMismatch {
+                        table: self.table.clone(),
+                        columns: unmatched,
+                    });
+                }
+            }
+            Some(_) => {
+                return Err(Error::TableExists {
+                    table: self.table.clone(),
+                })
+            }
+            None => {
+                let columns: Vec<_> = names
+                    .iter()
+                    .map(|name| format!("{} TEXT", quote(name)))
+                    .collect();
+                let create = format!("CREATE TABLE {} ({})", table, columns.join(", "));
+                self.connection.execute(&create, []).map_err(write_error)?;
+            }
+        }
+
+        for (index, name) in names.iter().take(self.source_columns).enumerate() {
+            let create_index = format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+                quote(&format!("{}_source_{}", self.table, index)),
+                table,
+                quote(name)
+            );
+            self.connection
+                .execute(&create_index, [])
+                .map_err(write_error)?;
+        }
+
+        let columns: Vec<_> = names.iter().map(|name| quote(name)).collect();
+        let values = vec!["?"; names.len()];
+        self.insert = Some(format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table,
+            columns.join(", "),
+            values.join(", ")
+        ));
+        self.columns = names.len();
+        self.connection.execute_batch("COMMIT").map_err(write_error)
+    }
+
+    /// Inserts a row, starting a new transaction every `TRANSACTION_SIZE`
+    /// rows. Missing fields are inserted as nulls, and fields beyond the end
+    /// of the header are dropped.
+    ///
+    /// Fields that aren't valid UTF-8 are inserted as blobs, rather than being
+    /// replaced.
+    pub fn write_row(&mut self, row: &ByteRecord) -> Result<()> {
+        let Some(insert) = &self.insert else {
+            return Ok(());
+        };
+        if self.rows.is_multiple_of(TRANSACTION_SIZE) {
+            if self.rows > 0 {
+                self.connection
+                    .execute_batch("COMMIT")
+                    .map_err(write_error)?;
+            }
+            self.connection
+                .execute_batch("BEGIN")
+                .map_err(write_error)?;
+        }
+
+        let values = (0..self.columns).map(|index| {
+            ToSqlOutput::Borrowed(match row.get(index) {
+                Some(field) if std::str::from_utf8(field).is_ok() => ValueRef::Text(field),
+                Some(field) => ValueRef::Blob(field),
+                None => ValueRef::Null,
+            })
+        });
+        let mut statement = self
+            .connection
+            .prepare_cached(insert)
+            .map_err(write_error)?;
+        statement
+            .execute(rusqlite::params_from_iter(values))
+            .map_err(write_error)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Commits the last transaction.
+    pub fn finish(self) -> Result<()> {
+        if self.rows > 0 {
+            self.connection
+                .execute_batch("COMMIT")
+                .map_err(write_error)?;
+        }
+        self.connection.close().map_err(|(_, err)| write_error(err))
+    }
+
+    /// The names of the table's columns, or `None` if the table doesn't exist.
+    fn table_columns(&self) -> Result<Option<Vec<String>>> {
+        let exists = self
+            .connection
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+                [&self.table],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(write_error)?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT name FROM pragma_table_info(?)")
+            .map_err(write_error)?;
+        let columns = statement
+            .query_map([&self.table], |row| row.get(0))
+            .and_then(Iterator::collect)
+            .map_err(write_error)?;
+        Ok(Some(columns))
+    }
+}
+
+/// Converts a SQLite error into a write error.
+fn write_error(err: rusqlite::Error) -> Error {
+    Error::Write(io::Error::other(err))
+}
+
+/// Quotes a SQL identifier (e.g. a table or column name).
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}